@@ -0,0 +1,719 @@
+use crate::disasm::{Op, Param};
+use crate::image::Image;
+use crate::intcoder::IntMode;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Libraries `.include <name>` finds without going to disk
+const LIBRARY: &[(&str, &str)] = &[
+    ("std.s", include_str!("std.s")),
+];
+
+// How deep includes and macro expansions may nest, to catch recursion
+const MAX_DEPTH: usize = 32;
+
+
+#[derive(Debug)]
+pub enum AsmError {
+    Io { source: String, err: io::Error },
+    Syntax { source: String, line: usize, msg: String },
+}
+
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::Io { source, err } => write!(f, "{}: {}", source, err),
+            AsmError::Syntax { source, line, msg } => write!(f, "{}:{}: {}", source, line, msg),
+        }
+    }
+}
+
+
+impl std::error::Error for AsmError {}
+
+
+impl From<AsmError> for io::Error {
+    fn from(err: AsmError) -> io::Error {
+        match err {
+            AsmError::Io { err, .. } => err,
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
+
+
+/*
+ * Line of source with comments removed, remembering where it came from
+ * so errors in included files and macro bodies point at the right place
+ */
+#[derive(Debug, Clone)]
+struct Line {
+    source: String,
+    line: usize,
+    text: String,
+}
+
+
+impl Line {
+    fn error(&self, msg: String) -> AsmError {
+        AsmError::Syntax { source: self.source.clone(), line: self.line, msg }
+    }
+}
+
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Line>,
+}
+
+
+/*
+ * Flattens source into plain lines by reading includes and expanding macros
+ */
+struct Expander {
+    macros: HashMap<String, Macro>,
+    lines: Vec<Line>,
+    expansions: usize,
+}
+
+
+/*
+ * Assembles program text into an image whose symbols are its labels.
+ * Syntax follows the disassembler's listings, one statement per line:
+ *
+ *     loop:   add [count], -1, [count]    # positional, immediate
+ *             out [rb-2]                  # relative
+ *             jnz [count], loop           # labels are addresses
+ *             halt
+ *     count:  .data 3
+ *
+ * Values are numbers, labels, 'c' characters or sums and differences of
+ * them. Directives are:
+ *
+ * .data a, "text", ...   words, a string giving one word per character
+ * .space n               n zero words
+ * .equ name, value       name a constant
+ * .include "file"        assemble a file here, relative to this one
+ * .include <lib>         same for a bundled library, e.g. <std.s>
+ * .macro name a, b       start a macro, ended by .endm. In its body \a is
+ *                        replaced by the argument given for a, and \@ by a
+ *                        number unique to each expansion for making labels
+ *
+ * Relative paths in `.include` are resolved against the directory of
+ * source, so it should be the file name the text was read from.
+ */
+pub fn assemble(text: &str, source: &str) -> Result<Image, AsmError> {
+    let mut expander = Expander { macros: HashMap::new(), lines: Vec::new(), expansions: 0 };
+    expander.file(text, source, 0)?;
+
+    Assembler::new(&expander.lines)?.emit()
+}
+
+
+pub fn assemble_file(path: &str) -> Result<Image, AsmError> {
+    match fs::read_to_string(path) {
+        Ok(text) => assemble(&text, path),
+        Err(err) => Err(AsmError::Io { source: path.to_string(), err })
+    }
+}
+
+
+impl Expander {
+    fn file(&mut self, text: &str, source: &str, depth: usize) -> Result<(), AsmError> {
+        let lines: Vec<Line> = text.lines().enumerate().map(|(i, t)| Line {
+            source: source.to_string(),
+            line: i + 1,
+            text: strip_comment(t).trim().to_string(),
+        }).collect();
+
+        self.expand(&lines, depth)
+    }
+
+    fn expand(&mut self, lines: &[Line], depth: usize) -> Result<(), AsmError> {
+        let mut i = 0;
+
+        while i < lines.len() {
+            let line = &lines[i];
+            let (label, rest) = split_label(&line.text);
+            let (word, args) = split_word(rest);
+            i += 1;
+
+            if depth > MAX_DEPTH {
+                return Err(line.error("includes or macros nested too deeply".to_string()));
+            }
+
+            match word {
+                ".macro" => {
+                    let (name, params) = split_word(args);
+                    let end = lines[i..].iter().position(|l| split_word(&l.text).0 == ".endm");
+
+                    let end = match end {
+                        Some(end) => i + end,
+                        None => return Err(line.error(format!("macro `{}` has no .endm", name)))
+                    };
+
+                    if name.is_empty() || label.is_some() {
+                        return Err(line.error("expected `.macro name params`".to_string()));
+                    }
+
+                    let params = split_args(params).into_iter().map(|p| p.trim().to_string()).collect();
+                    self.macros.insert(name.to_string(), Macro { params, body: lines[i..end].to_vec() });
+                    i = end + 1;
+                    continue;
+                },
+                ".endm" => return Err(line.error(".endm without .macro".to_string())),
+                _ => {}
+            };
+
+            if let Some(label) = label {
+                self.lines.push(Line { text: format!("{}:", label), ..line.clone() });
+            }
+
+            if word == ".include" {
+                let (text, source) = self.include(line, args.trim())?;
+                self.file(&text, &source, depth + 1)?;
+            } else if let Some(body) = self.invoke(line, word, args)? {
+                self.expand(&body, depth + 1)?;
+            } else if ! rest.is_empty() {
+                self.lines.push(Line { text: rest.to_string(), ..line.clone() });
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Text and source name of the file named by an include
+     */
+    fn include(&self, line: &Line, name: &str) -> Result<(String, String), AsmError> {
+        if let Some(lib) = name.strip_prefix('<').and_then(|n| n.strip_suffix('>')) {
+            return match LIBRARY.iter().find(|(n, _)| *n == lib) {
+                Some((n, text)) => Ok((text.to_string(), format!("<{}>", n))),
+                None => Err(line.error(format!("no library `{}`", lib)))
+            };
+        }
+
+        let path = match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+            Some(path) => Path::new(&line.source).parent().unwrap_or(Path::new("")).join(path),
+            None => return Err(line.error("expected `.include \"file\"` or `.include <lib>`".to_string()))
+        };
+
+        let source = path.to_string_lossy().to_string();
+
+        match fs::read_to_string(&path) {
+            Ok(text) => Ok((text, source)),
+            Err(err) => Err(AsmError::Io { source, err })
+        }
+    }
+
+    /*
+     * Body of the macro called word with its arguments filled in, None if
+     * there is no such macro
+     */
+    fn invoke(&mut self, line: &Line, word: &str, args: &str) -> Result<Option<Vec<Line>>, AsmError> {
+        let mac = match self.macros.get(word) {
+            Some(mac) => mac,
+            None => return Ok(None)
+        };
+
+        let args: Vec<String> = split_args(args).into_iter().map(|a| a.trim().to_string()).collect();
+
+        if args.len() != mac.params.len() {
+            return Err(line.error(format!("macro `{}` takes {} arguments, got {}", word, mac.params.len(), args.len())));
+        }
+
+        self.expansions += 1;
+        let mut body = Vec::new();
+
+        for l in &mac.body {
+            let mut text = String::new();
+            let mut chars = l.text.chars().peekable();
+
+            while let Some(c) = chars.next() {
+                if c != '\\' || chars.peek().is_none_or(|n| ! is_ident(*n) && *n != '@') {
+                    text.push(c);
+                    continue;
+                }
+
+                if chars.next_if_eq(&'@').is_some() {
+                    text.push_str(&self.expansions.to_string());
+                    continue;
+                }
+
+                let mut name = String::new();
+                while let Some(n) = chars.next_if(|n| is_ident(*n)) {
+                    name.push(n);
+                }
+
+                match mac.params.iter().position(|p| *p == name) {
+                    Some(p) => text.push_str(&args[p]),
+                    None => return Err(l.error(format!("macro `{}` has no parameter `{}`", word, name)))
+                };
+            }
+
+            body.push(Line { text, ..l.clone() });
+        }
+
+        Ok(Some(body))
+    }
+}
+
+
+/*
+ * Two passes over expanded lines, the first to find where every label is
+ * and the second to emit words
+ */
+struct Assembler<'a> {
+    lines: &'a [Line],
+    labels: BTreeMap<String, usize>,
+    equs: HashMap<String, (String, &'a Line)>,
+    size: usize,
+}
+
+
+impl<'a> Assembler<'a> {
+    fn new(lines: &'a [Line]) -> Result<Assembler<'a>, AsmError> {
+        let mut asm = Assembler { lines, labels: BTreeMap::new(), equs: HashMap::new(), size: 0 };
+
+        for line in lines {
+            let (label, rest) = split_label(&line.text);
+            let (word, args) = split_word(rest);
+
+            if let Some(label) = label {
+                if asm.labels.insert(label.to_string(), asm.size).is_some() {
+                    return Err(line.error(format!("label `{}` defined twice", label)));
+                }
+            }
+
+            asm.size += match word {
+                "" => 0,
+                ".data" => split_args(args).iter().map(|a| match string(a) {
+                    Some(s) => s.len(),
+                    None => 1
+                }).sum(),
+                ".space" => match args.trim().parse::<usize>() {
+                    Ok(n) => n,
+                    Err(_) => return Err(line.error(format!("bad size `{}`", args.trim())))
+                },
+                ".equ" => {
+                    let (name, value) = match args.split_once(',') {
+                        Some((n, v)) => (n.trim(), v.trim()),
+                        None => return Err(line.error("expected `.equ name, value`".to_string()))
+                    };
+                    asm.equs.insert(name.to_string(), (value.to_string(), line));
+                    0
+                },
+                _ => match op(word) {
+                    Some(op) => 1 + op.arity(),
+                    None => return Err(line.error(format!("unknown instruction `{}`", word)))
+                }
+            };
+        }
+
+        Ok(asm)
+    }
+
+    fn emit(&self) -> Result<Image, AsmError> {
+        let mut words = Vec::with_capacity(self.size);
+
+        for line in self.lines {
+            let (word, args) = split_word(split_label(&line.text).1);
+
+            match word {
+                "" | ".equ" => {},
+                ".space" => words.resize(words.len() + args.trim().parse::<usize>().unwrap_or(0), 0),
+                ".data" => for a in split_args(args) {
+                    match string(&a) {
+                        Some(s) => words.extend(s),
+                        None => words.push(self.eval(&a, line, 0)?)
+                    };
+                },
+                _ => {
+                    let op = op(word).unwrap_or(Op::Halt);
+                    let args = split_args(args);
+
+                    if args.len() != op.arity() {
+                        return Err(line.error(format!("`{}` takes {} operands, got {}", word, op.arity(), args.len())));
+                    }
+
+                    let mut code = op.code();
+                    let mut scale = 100;
+                    let mut params = Vec::new();
+
+                    for (i, a) in args.iter().enumerate() {
+                        let p = self.param(a, line)?;
+
+                        if p.mode == IntMode::Imm && op.writes() == Some(i) {
+                            return Err(line.error(format!("`{}` can't write to immediate `{}`", word, a.trim())));
+                        }
+
+                        code += scale * match p.mode {
+                            IntMode::Pos => 0,
+                            IntMode::Imm => 1,
+                            IntMode::Rel => 2,
+                        };
+                        scale *= 10;
+                        params.push(p.val);
+                    }
+
+                    words.push(code);
+                    words.extend(params);
+                }
+            };
+        }
+
+        Ok(Image { entry: 0, words, symbols: self.labels.clone() })
+    }
+
+    /*
+     * Operand as a parameter: [rb+x] relative, [x] positional or plain x
+     */
+    fn param(&self, text: &str, line: &Line) -> Result<Param, AsmError> {
+        let text = text.trim();
+
+        let inner = match text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            Some(inner) => inner.trim(),
+            None => return Ok(Param { mode: IntMode::Imm, val: self.eval(text, line, 0)? })
+        };
+
+        match inner.strip_prefix("rb") {
+            Some(off) if off.trim().is_empty() => Ok(Param { mode: IntMode::Rel, val: 0 }),
+            Some(off) if off.trim_start().starts_with(['+', '-']) => {
+                Ok(Param { mode: IntMode::Rel, val: self.eval(off, line, 0)? })
+            },
+            _ => Ok(Param { mode: IntMode::Pos, val: self.eval(inner, line, 0)? })
+        }
+    }
+
+    /*
+     * Value of a sum of numbers, characters and symbols
+     */
+    fn eval(&self, expr: &str, line: &Line, depth: usize) -> Result<i64, AsmError> {
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut term = true;
+        let mut chars = expr.trim().chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                _ if c.is_whitespace() => {},
+                '+' => term = true,
+                '-' if term => sign = -sign,
+                '-' => {
+                    sign = -1;
+                    term = true;
+                },
+                _ if ! term => return Err(line.error(format!("unexpected `{}` in `{}`", c, expr.trim()))),
+                '\'' => {
+                    let val = match chars.next() {
+                        Some('\\') => chars.next().and_then(escape),
+                        c => c
+                    };
+
+                    match (val, chars.next()) {
+                        (Some(v), Some('\'')) => total = total.wrapping_add(sign * v as i64),
+                        _ => return Err(line.error(format!("bad character in `{}`", expr.trim())))
+                    };
+                    sign = 1;
+                    term = false;
+                },
+                _ if is_ident(c) => {
+                    let mut name = c.to_string();
+                    while let Some(n) = chars.next_if(|n| is_ident(*n)) {
+                        name.push(n);
+                    }
+
+                    let val = self.symbol(&name, line, depth)?;
+                    total = total.wrapping_add(sign.wrapping_mul(val));
+                    sign = 1;
+                    term = false;
+                },
+                _ => return Err(line.error(format!("unexpected `{}` in `{}`", c, expr.trim())))
+            };
+        }
+
+        if term {
+            return Err(line.error(format!("missing value in `{}`", expr.trim())));
+        }
+
+        Ok(total)
+    }
+
+    fn symbol(&self, name: &str, line: &Line, depth: usize) -> Result<i64, AsmError> {
+        if name.starts_with(|c: char| c.is_ascii_digit()) {
+            return name.parse().map_err(|_| line.error(format!("invalid number `{}`", name)));
+        }
+
+        if let Some(addr) = self.labels.get(name) {
+            return Ok(*addr as i64);
+        }
+
+        match self.equs.get(name) {
+            Some(_) if depth > MAX_DEPTH => Err(line.error(format!("`{}` is defined in terms of itself", name))),
+            Some((value, at)) => self.eval(value, at, depth + 1),
+            None if name == "__end" => Ok(self.size as i64),
+            None => Err(line.error(format!("unknown symbol `{}`", name)))
+        }
+    }
+}
+
+
+/*
+ * Instruction with the given disassembler name
+ */
+fn op(name: &str) -> Option<Op> {
+    (1..=99).filter_map(Op::new).find(|op| op.name() == name)
+}
+
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+
+fn escape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        '0' => Some('\0'),
+        '\\' | '\'' | '"' => Some(c),
+        _ => None
+    }
+}
+
+
+/*
+ * Line up to any `#` comment, ignoring any inside quotes
+ */
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), _) if c == q => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '#') => return &text[..i],
+            _ => {}
+        };
+    }
+
+    text
+}
+
+
+/*
+ * Leading `label:` if there is one, and the rest of the line
+ */
+fn split_label(text: &str) -> (Option<&str>, &str) {
+    let end = text.find(|c: char| ! is_ident(c)).unwrap_or(text.len());
+
+    match text[end..].strip_prefix(':') {
+        Some(rest) if end > 0 => (Some(&text[..end]), rest.trim()),
+        _ => (None, text)
+    }
+}
+
+
+/*
+ * First word of a statement and everything after it
+ */
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, "")
+    }
+}
+
+
+/*
+ * Comma separated operands, leaving commas inside quotes alone
+ */
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+
+    if text.trim().is_empty() {
+        return args;
+    }
+
+    for c in text.chars() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), _) if c == q => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, ',') => {
+                args.push(arg.clone());
+                arg.clear();
+                continue;
+            },
+            _ => {}
+        };
+
+        arg.push(c);
+    }
+
+    args.push(arg);
+    args
+}
+
+
+/*
+ * Words of a "string" operand, None if it isn't one
+ */
+fn string(text: &str) -> Option<Vec<i64>> {
+    let inner = text.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut words = Vec::new();
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => escape(chars.next()?)?,
+            _ => c
+        };
+        words.push(c as i64);
+    }
+
+    Some(words)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Candidate;
+
+    fn run(text: &str, input: &str) -> String {
+        let image = assemble(text, "test.s").unwrap();
+        let inputs: Vec<i64> = input.bytes().map(|b| b as i64).collect();
+        let (_, outputs) = Candidate::inputs(&inputs).run(&image.words);
+
+        outputs.iter().map(|o| *o as u8 as char).collect()
+    }
+
+    #[test]
+    fn test_assemble() {
+        let text = "
+            # count down from 3
+            loop:   out [count]
+                    add [count], -1, [count]
+                    jnz [count], loop
+                    out [rb+msg-1]     # relative with base 0
+                    halt
+            count:  .data 3
+            msg:    .data \"a,b\\n\", 'c', msg + 1
+                    .space 2
+        ";
+        let image = assemble(text, "test.s").unwrap();
+
+        assert_eq!(image.words, vec!(4,12,1001,12,-1,12,1005,12,0,204,12,99,
+                                     3,97,44,98,10,99,14,0,0));
+        assert_eq!(image.symbols["count"], 12);
+        assert_eq!(image.symbols["msg"], 13);
+        assert_eq!(crate::disasm::listing(&image.words[..12]).lines().count(), 5);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| assemble(text, "bad.s").unwrap_err().to_string();
+
+        assert_eq!(error("halt\nadd 1, 2, 3"), "bad.s:2: `add` can't write to immediate `3`");
+        assert_eq!(error("jnz 1, nowhere"), "bad.s:1: unknown symbol `nowhere`");
+        assert_eq!(error("out 1, 2"), "bad.s:1: `out` takes 1 operands, got 2");
+        assert_eq!(error("a: halt\na: halt"), "bad.s:2: label `a` defined twice");
+        assert_eq!(error("mov 1, [2]"), "bad.s:1: unknown instruction `mov`");
+        assert_eq!(error(".include <nope.s>"), "bad.s:1: no library `nope.s`");
+        assert_eq!(error(".macro m a\nout \\b\n.endm\nm 1"), "bad.s:2: macro `m` has no parameter `b`");
+        assert_eq!(error(".macro m\nm\n.endm\nm"), "bad.s:2: includes or macros nested too deeply");
+        assert!(error(".include \"missing.s\"").starts_with("missing.s: "));
+    }
+
+    #[test]
+    fn test_macros() {
+        let text = "
+            .equ newline, 10
+            .macro print_twice c, end
+                    out \\c
+            again\\@: out \\c
+                    out \\end
+            .endm
+            print_twice 'x', newline
+            print_twice '-', ','
+                    halt
+        ";
+        assert_eq!(run(text, ""), "xx\n--,");
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("asm-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.s"), ".include \"lib/a.s\"\nhalt\n").unwrap();
+        fs::write(dir.join("lib/a.s"), ".include \"b.s\"\nout 'a'\n").unwrap();
+        fs::write(dir.join("lib/b.s"), "out 'b'\n").unwrap();
+
+        let image = assemble_file(dir.join("main.s").to_str().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(image.unwrap().words, vec!(104,98,104,97,99));
+    }
+
+    #[test]
+    fn test_std() {
+        let text = "
+            .include <std.s>
+                    push buf
+                    call read_line
+                    drop 1
+                    push [std_ret]
+                    call print_int
+                    drop 1
+                    out ' '
+                    push buf
+                    call print_str
+                    drop 1
+                    out ' '
+                    push -9087654321
+                    call print_int
+                    drop 1
+                    out ' '
+                    push 0
+                    call print_int
+                    drop 1
+                    out ' '
+                    push 10
+                    call fact
+                    drop 1
+                    push [std_ret]
+                    call print_int
+                    drop 1
+                    halt
+
+            # fact(n): n! in [std_ret], recursively
+            fact:   jnz [rb-2], fact_rec
+                    add 1, 0, [std_ret]
+                    ret
+            fact_rec:
+                    push [rb-2]
+                    add [rb-1], -1, [rb-1]
+                    call fact
+                    drop 1
+                    mul [std_ret], [rb-2], [std_ret]
+                    ret
+
+            buf:    .space 80
+        ";
+        assert_eq!(run(text, "hello, world\n"), "12 hello, world -9087654321 0 3628800");
+    }
+}
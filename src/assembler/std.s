@@ -0,0 +1,139 @@
+# Standard library
+#
+# Include it as the first line of a program with `.include <std.s>`. It
+# starts a stack on the relative base just past the end of the program
+# and jumps over its own routines, so execution carries on with the code
+# after the include.
+#
+# Routines take their arguments on the stack: push them in order, `call`
+# the routine, then `drop` them. Inside a routine with n arguments the
+# first is at [rb-n-1] and the last at [rb-2], with the return address at
+# [rb-1]. Results are left in [std_ret]. Routines keep their scratch
+# values in fixed words, so they aren't reentrant.
+
+# stack grows upwards, [rb] is the next free word
+.macro push val
+        add \val, 0, [rb]
+        arb 1
+.endm
+
+.macro pop dst
+        arb -1
+        add [rb], 0, \dst
+.endm
+
+.macro drop n
+        arb -\n
+.endm
+
+# push the return address and jump, the way disasm expects a call to look
+.macro call f
+        add std_call\@, 0, [rb]
+        arb 1
+        jz 0, \f
+std_call\@:
+.endm
+
+.macro ret
+        arb -1
+        jz 0, [rb]
+.endm
+
+        arb __end
+        jz 0, std_end
+
+
+# one decimal digit of [std_n] for the power of ten p, skipping leading zeros
+.macro std_digit p
+        add 0, 0, [std_d]
+std_digit\@:
+        lt [std_n], \p, [std_t]
+        jnz [std_t], std_digit_done\@
+        add [std_n], -\p, [std_n]
+        add [std_d], 1, [std_d]
+        jz 0, std_digit\@
+std_digit_done\@:
+        add [std_started], [std_d], [std_t]
+        jz [std_t], std_digit_skip\@
+        add 1, 0, [std_started]
+        add [std_d], '0', [std_t]
+        out [std_t]
+std_digit_skip\@:
+.endm
+
+# print_int(n): output n in decimal as ASCII
+print_int:
+        add [rb-2], 0, [std_n]
+        lt [std_n], 0, [std_t]
+        jz [std_t], std_print_digits
+        out '-'
+        mul [std_n], -1, [std_n]
+std_print_digits:
+        add 0, 0, [std_started]
+        std_digit 1000000000000000000
+        std_digit 100000000000000000
+        std_digit 10000000000000000
+        std_digit 1000000000000000
+        std_digit 100000000000000
+        std_digit 10000000000000
+        std_digit 1000000000000
+        std_digit 100000000000
+        std_digit 10000000000
+        std_digit 1000000000
+        std_digit 100000000
+        std_digit 10000000
+        std_digit 1000000
+        std_digit 100000
+        std_digit 10000
+        std_digit 1000
+        std_digit 100
+        std_digit 10
+        add [std_n], '0', [std_t]
+        out [std_t]
+        ret
+
+# print_str(s): output characters from address s up to a 0
+print_str:
+        add [rb-2], 0, [std_ptr]
+std_print_next:
+        add [std_ptr], 0, [std_print_load+1]
+std_print_load:
+        add [0], 0, [std_c]
+        jz [std_c], std_print_done
+        out [std_c]
+        add [std_ptr], 1, [std_ptr]
+        jz 0, std_print_next
+std_print_done:
+        ret
+
+# read_line(buf): read characters into buf up to a newline, or -1 for no
+# input, and end them with a 0. The length read is left in [std_ret].
+read_line:
+        add [rb-2], 0, [std_ptr]
+        add 0, 0, [std_ret]
+std_read_next:
+        in [std_c]
+        eq [std_c], '\n', [std_t]
+        jnz [std_t], std_read_done
+        eq [std_c], -1, [std_t]
+        jnz [std_t], std_read_done
+        add [std_ptr], 0, [std_read_store+3]
+std_read_store:
+        add [std_c], 0, [0]
+        add [std_ptr], 1, [std_ptr]
+        add [std_ret], 1, [std_ret]
+        jz 0, std_read_next
+std_read_done:
+        add [std_ptr], 0, [std_read_end+3]
+std_read_end:
+        add 0, 0, [0]
+        ret
+
+std_ret:        .data 0
+std_n:          .data 0
+std_t:          .data 0
+std_d:          .data 0
+std_started:    .data 0
+std_c:          .data 0
+std_ptr:        .data 0
+std_end:
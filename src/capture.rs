@@ -0,0 +1,141 @@
+use crate::intcoder::Intcode;
+use crate::search::Candidate;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+
+/*
+ * One value delivered to a machine, numbered in the order values were
+ * delivered. from is None for values sent in from outside the network.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Message {
+    pub seq: u64,
+    pub from: Option<usize>,
+    pub to: usize,
+    pub value: i64,
+}
+
+
+/*
+ * A log of traffic between machines. Saved as text, a line per message:
+ *
+ *     seq from to value
+ *
+ * with `-` for a from outside the network.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Capture {
+    pub messages: Vec<Message>,
+}
+
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture::default()
+    }
+
+    pub fn record(&mut self, from: Option<usize>, to: usize, value: i64) {
+        let seq = self.messages.len() as u64;
+
+        self.messages.push(Message { seq, from, to, value });
+    }
+
+    /*
+     * Everything delivered to one machine, in the order it was delivered
+     */
+    pub fn inputs(&self, node: usize) -> Vec<i64> {
+        let mut messages: Vec<&Message> = self.messages.iter().filter(|m| m.to == node).collect();
+        messages.sort_by_key(|m| m.seq);
+
+        messages.iter().map(|m| m.value).collect()
+    }
+
+    /*
+     * Runs prog alone with the inputs one machine was given, until it
+     * halts or runs out. Machines on a packet network also read -1 when
+     * nothing had arrived, which isn't captured, so they may not follow
+     * quite the same path.
+     */
+    pub fn replay(&self, prog: &[i64], node: usize) -> (Intcode, Vec<i64>) {
+        Candidate::inputs(&self.inputs(node)).run(prog)
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for m in self.messages.iter() {
+            let from = match m.from {
+                Some(from) => from.to_string(),
+                None => "-".to_string()
+            };
+
+            writeln!(w, "{} {} {} {}", m.seq, from, m.to, m.value)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: BufRead>(r: &mut R) -> io::Result<Capture> {
+        let mut messages = Vec::new();
+
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let bad = || io::Error::new(io::ErrorKind::InvalidData, format!("bad capture line {}: {}", i + 1, line));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            if fields.len() != 4 {
+                return Err(bad());
+            }
+
+            let from = match fields[1] {
+                "-" => None,
+                from => Some(from.parse().map_err(|_| bad())?)
+            };
+
+            messages.push(Message {
+                seq: fields[0].parse().map_err(|_| bad())?,
+                from,
+                to: fields[2].parse().map_err(|_| bad())?,
+                value: fields[3].parse().map_err(|_| bad())?,
+            });
+        }
+
+        Ok(Capture { messages })
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        self.write(&mut File::create(path)?)
+    }
+
+    pub fn open(path: &str) -> io::Result<Capture> {
+        Capture::read(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        let mut capture = Capture::new();
+        capture.record(None, 1, 5);
+        capture.record(Some(1), 0, -12);
+        capture.record(Some(0), 1, 7);
+
+        let mut text = Vec::new();
+        capture.write(&mut text).unwrap();
+        assert_eq!(String::from_utf8(text.clone()).unwrap(), "0 - 1 5\n1 1 0 -12\n2 0 1 7\n");
+
+        assert_eq!(Capture::read(&mut &text[..]).unwrap(), capture);
+        assert_eq!(capture.inputs(1), vec!(5, 7));
+
+        assert!(Capture::read(&mut &b"0 - 1\n"[..]).is_err());
+        assert!(Capture::read(&mut &b"0 x 1 2\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::disasm::{self, Instr, Op};
+use crate::intcoder::IntMode;
+use std::collections::{BTreeMap, BTreeSet};
+
+
+/*
+ * How control leaves a basic block. The jump instruction ending a block is
+ * kept here rather than in the block's instruction list.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exit {
+    Goto(usize),
+    Branch { jump: Instr, taken: Option<usize>, fall: usize },
+    Call { target: usize, ret: usize },
+    Jump(Instr),
+    Halt,
+    End,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub instrs: Vec<Instr>,
+    pub exit: Exit,
+}
+
+
+impl Block {
+
+    /*
+     * Blocks control can move to next within the same function, a call
+     * continues at its return address rather than the callee
+     */
+    pub fn succs(&self) -> Vec<usize> {
+        match &self.exit {
+            Exit::Goto(t) => vec!(*t),
+            Exit::Branch { taken: Some(t), fall, .. } if t != fall => vec!(*t, *fall),
+            Exit::Branch { fall, .. } => vec!(*fall),
+            Exit::Call { ret, .. } => vec!(*ret),
+            Exit::Jump(_) | Exit::Halt | Exit::End => Vec::new(),
+        }
+    }
+}
+
+
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+    pub functions: BTreeSet<usize>,
+}
+
+
+impl Cfg {
+
+    /*
+     * Splits the disassembled program into basic blocks. A block ends at any
+     * jump or halt, or right before another block's first instruction. A
+     * jump into data still gets an empty block ending in Exit::End, so every
+     * successor has a block.
+     *
+     * Calls are recognized by the idiom compiled programs use: a constant
+     * write of the return address (usually onto the relative base stack)
+     * followed in the same block by an unconditional jump. The jump target is
+     * recorded as a function entry.
+     */
+    pub fn new(prog: &[i64]) -> Cfg {
+        let instrs = disasm::disassemble(prog);
+        let mut leaders = BTreeSet::new();
+        let mut blocks = BTreeMap::new();
+        let mut functions = BTreeSet::new();
+
+        leaders.insert(0);
+        for instr in instrs.values() {
+            if instr.is_jump() {
+                leaders.insert(instr.next());
+                if let Some(t) = instr.target() {
+                    leaders.insert(t);
+                }
+            }
+        }
+
+        for &start in leaders.iter() {
+            let mut addr = start;
+            let mut body = Vec::new();
+
+            let exit = loop {
+                let instr = match instrs.get(&addr) {
+                    Some(i) => i.clone(),
+                    None => break Exit::End
+                };
+
+                if instr.op == Op::Halt {
+                    break Exit::Halt;
+                }
+
+                if instr.is_jump() {
+                    break Cfg::exit(&body, instr);
+                }
+
+                addr = instr.next();
+                body.push(instr);
+
+                if leaders.contains(&addr) {
+                    break Exit::Goto(addr);
+                }
+            };
+
+            if let Exit::Call { target, .. } = exit {
+                functions.insert(target);
+            }
+
+            blocks.insert(start, Block { start, instrs: body, exit });
+        }
+
+        Cfg { blocks, functions }
+    }
+
+    fn exit(body: &[Instr], jump: Instr) -> Exit {
+        let target = jump.target();
+        let next = jump.next();
+
+        if ! jump.always_jumps() {
+            return Exit::Branch { jump, taken: target, fall: next };
+        }
+
+        match target {
+            Some(target) => {
+                if body.iter().any(|i| i.constant() == Some(next as i64)) {
+                    Exit::Call { target, ret: next }
+                } else {
+                    Exit::Goto(target)
+                }
+            },
+            None => Exit::Jump(jump)
+        }
+    }
+
+    /*
+     * Blocks belonging to the function starting at entry, found by following
+     * successors without descending into calls
+     */
+    pub fn function(&self, entry: usize) -> BTreeSet<usize> {
+        let mut nodes = BTreeSet::new();
+        let mut todo = vec!(entry);
+
+        while let Some(b) = todo.pop() {
+            if ! self.blocks.contains_key(&b) || ! nodes.insert(b) {
+                continue;
+            }
+
+            todo.extend(self.blocks[&b].succs());
+        }
+
+        nodes
+    }
+
+    /*
+     * Relative base of every block in a function, relative to its value on
+     * entry. None when the base is changed by a non-constant amount.
+     */
+    pub fn rel_offsets(&self, entry: usize) -> BTreeMap<usize, Option<i64>> {
+        let mut offsets = BTreeMap::new();
+        let mut todo = vec!((entry, Some(0)));
+
+        while let Some((b, offset)) = todo.pop() {
+            if offsets.contains_key(&b) {
+                continue;
+            }
+
+            let block = match self.blocks.get(&b) {
+                Some(block) => block,
+                None => continue
+            };
+
+            offsets.insert(b, offset);
+
+            let mut out = offset;
+            for instr in block.instrs.iter().filter(|i| i.op == Op::Rel) {
+                out = match (out, instr.params[0].mode) {
+                    (Some(o), IntMode::Imm) => Some(o + instr.params[0].val),
+                    _ => None
+                };
+            }
+
+            for s in block.succs() {
+                todo.push((s, out));
+            }
+        }
+
+        offsets
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks() {
+        //             0 1 2    3 4 5   6 7  8   9 10
+        let jmp = vec!(3,3,1105,0,8,104,1,99,104,0,99);
+        let cfg = Cfg::new(&jmp);
+        let starts: Vec<usize> = cfg.blocks.keys().cloned().collect();
+        assert_eq!(starts, vec!(0,5,8));
+        assert_eq!(cfg.blocks[&0].succs(), vec!(8,5));
+        assert_eq!(cfg.blocks[&5].exit, Exit::Halt);
+    }
+
+    #[test]
+    fn test_call() {
+        // main: push return address 9, call 12, output and halt
+        // 12:   grow stack, output 7, shrink stack and return
+        let prog = vec!(109,100,21101,0,9,0,1105,1,12,
+                        104,1,99,
+                        109,1,104,7,109,-1,2106,0,0);
+        let cfg = Cfg::new(&prog);
+        assert!(cfg.functions.contains(&12));
+
+        match &cfg.blocks[&0].exit {
+            Exit::Call { target, ret, .. } => assert_eq!((*target, *ret), (12, 9)),
+            e => panic!("expected call, got {:?}", e)
+        };
+
+        let func: Vec<usize> = cfg.function(12).into_iter().collect();
+        assert_eq!(func, vec!(12));
+        assert_eq!(cfg.rel_offsets(12)[&12], Some(0));
+    }
+}
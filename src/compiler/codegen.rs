@@ -0,0 +1,414 @@
+use crate::compiler::CompileError;
+use crate::compiler::parser::{BinOp, Expr, Function, Stmt};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+
+/*
+ * Where a value lives while a function runs. Slots are words of the
+ * current frame, given as offsets from the relative base.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Imm(i64),
+    Slot(i64),
+}
+
+
+impl Operand {
+    fn asm(&self) -> String {
+        match self {
+            Operand::Imm(n) => n.to_string(),
+            Operand::Slot(n) if *n < 0 => format!("[rb-{}]", -n),
+            Operand::Slot(n) => format!("[rb+{}]", n),
+        }
+    }
+}
+
+
+/*
+ * Generates assembly for a whole program. Each function's frame starts at
+ * the relative base, which stays put while its body runs:
+ *
+ *     [rb-n-1] .. [rb-2]   the n arguments
+ *     [rb-1]               return address
+ *     [rb+0] ..            locals and temporaries, `<fn>_frame` words
+ *
+ * A call stores its arguments and return address just past the caller's
+ * frame, moves the relative base up to make that the callee's frame and
+ * moves it back once the callee returns. Results come back in [__ret].
+ */
+pub struct Codegen<'a> {
+    source: &'a str,
+    arity: HashMap<String, usize>,
+    out: String,
+    labels: usize,
+}
+
+
+/*
+ * State while generating one function
+ */
+struct Frame {
+    name: String,
+    scopes: Vec<HashMap<String, i64>>,
+    next: i64,
+    size: i64,
+    loops: Vec<(String, String)>,
+}
+
+
+impl<'a> Codegen<'a> {
+    pub fn new(source: &'a str) -> Codegen<'a> {
+        Codegen { source, arity: HashMap::new(), out: String::new(), labels: 0 }
+    }
+
+    pub fn program(mut self, functions: &[Function]) -> Result<String, CompileError> {
+        for f in functions {
+            if self.arity.insert(f.name.clone(), f.params.len()).is_some() {
+                return Err(self.error(f.line, format!("function `{}` defined twice", f.name)));
+            }
+        }
+
+        if self.arity.get("main") != Some(&0) {
+            return Err(self.error(1, "no `fn main()` to start from".to_string()));
+        }
+
+        self.emit("arb __end");
+        self.emit("add __start, 0, [rb]");
+        self.emit("arb 1");
+        self.emit("jz 0, fn_main");
+        self.label("__start");
+        self.emit("halt");
+        self.label("__ret");
+        self.emit(".data 0");
+
+        for f in functions {
+            self.function(f)?;
+        }
+
+        Ok(self.out)
+    }
+
+    fn function(&mut self, f: &Function) -> Result<(), CompileError> {
+        let n = f.params.len() as i64;
+        let args = f.params.iter().enumerate().map(|(i, p)| (p.clone(), i as i64 - n - 1)).collect();
+
+        let mut frame = Frame {
+            name: f.name.clone(),
+            scopes: vec!(args),
+            next: 0,
+            size: 0,
+            loops: Vec::new(),
+        };
+
+        self.out.push('\n');
+        self.label(&format!("fn_{}", f.name));
+        self.block(&mut frame, &f.body)?;
+        self.emit("add 0, 0, [__ret]");
+        self.emit("jz 0, [rb-1]");
+        self.emit(&format!(".equ fn_{}_frame, {}", f.name, frame.size));
+
+        Ok(())
+    }
+
+    fn block(&mut self, frame: &mut Frame, stmts: &[Stmt]) -> Result<(), CompileError> {
+        let mark = frame.next;
+        frame.scopes.push(HashMap::new());
+
+        for s in stmts {
+            self.stmt(frame, s)?;
+        }
+
+        frame.scopes.pop();
+        frame.next = mark;
+
+        Ok(())
+    }
+
+    fn stmt(&mut self, frame: &mut Frame, stmt: &Stmt) -> Result<(), CompileError> {
+        let mark = frame.next;
+
+        match stmt {
+            Stmt::Let(name, e) => {
+                let val = self.expr(frame, e)?;
+                frame.next = mark;
+
+                let slot = frame.temp();
+                self.copy(val, slot);
+                frame.scopes.last_mut().unwrap().insert(name.clone(), slot);
+                return Ok(());
+            },
+            Stmt::Assign(name, e, line) => {
+                let slot = match frame.lookup(name) {
+                    Some(slot) => slot,
+                    None => return Err(self.error(*line, format!("unknown variable `{}`", name)))
+                };
+                let val = self.expr(frame, e)?;
+                self.copy(val, slot);
+            },
+            Stmt::If(cond, then, other) => {
+                let (other_label, end) = (self.new_label(), self.new_label());
+
+                let c = self.expr(frame, cond)?;
+                frame.next = mark;
+                self.emit(&format!("jz {}, {}", c.asm(), other_label));
+                self.block(frame, then)?;
+                self.emit(&format!("jz 0, {}", end));
+                self.label(&other_label);
+                self.block(frame, other)?;
+                self.label(&end);
+            },
+            Stmt::While(cond, body) => {
+                let (top, end) = (self.new_label(), self.new_label());
+
+                self.label(&top);
+                let c = self.expr(frame, cond)?;
+                frame.next = mark;
+                self.emit(&format!("jz {}, {}", c.asm(), end));
+
+                frame.loops.push((top.clone(), end.clone()));
+                self.block(frame, body)?;
+                frame.loops.pop();
+
+                self.emit(&format!("jz 0, {}", top));
+                self.label(&end);
+            },
+            Stmt::Break(line) | Stmt::Continue(line) => {
+                let target = match (frame.loops.last(), stmt) {
+                    (Some((_, end)), Stmt::Break(_)) => end.clone(),
+                    (Some((top, _)), _) => top.clone(),
+                    (None, _) => return Err(self.error(*line, "`break` or `continue` outside a loop".to_string()))
+                };
+                self.emit(&format!("jz 0, {}", target));
+            },
+            Stmt::Return(e) => {
+                let val = match e {
+                    Some(e) => self.expr(frame, e)?,
+                    None => Operand::Imm(0)
+                };
+                self.emit(&format!("add {}, 0, [__ret]", val.asm()));
+                self.emit("jz 0, [rb-1]");
+            },
+            Stmt::Expr(e) => {
+                self.expr(frame, e)?;
+            }
+        };
+
+        frame.next = mark;
+
+        Ok(())
+    }
+
+    /*
+     * Generates code for an expression and says where its value ends up
+     */
+    fn expr(&mut self, frame: &mut Frame, e: &Expr) -> Result<Operand, CompileError> {
+        match e {
+            Expr::Num(n) => Ok(Operand::Imm(*n)),
+            Expr::Var(name, line) => match frame.lookup(name) {
+                Some(slot) => Ok(Operand::Slot(slot)),
+                None => Err(self.error(*line, format!("unknown variable `{}`", name)))
+            },
+            Expr::Neg(e) => {
+                let a = self.expr(frame, e)?;
+                Ok(self.op("mul", a, Operand::Imm(-1), frame))
+            },
+            Expr::Not(e) => {
+                let a = self.expr(frame, e)?;
+                Ok(self.op("eq", a, Operand::Imm(0), frame))
+            },
+            Expr::Binary(BinOp::And, l, r) | Expr::Binary(BinOp::Or, l, r) => {
+                let and = matches!(e, Expr::Binary(BinOp::And, _, _));
+                let (skip, end) = (self.new_label(), self.new_label());
+                let (jump, short) = if and { ("jz", 0) } else { ("jnz", 1) };
+                let t = frame.temp();
+
+                let a = self.expr(frame, l)?;
+                self.emit(&format!("{} {}, {}", jump, a.asm(), skip));
+                let b = self.expr(frame, r)?;
+                self.emit(&format!("{} {}, {}", jump, b.asm(), skip));
+                self.emit(&format!("add {}, 0, [rb+{}]", 1 - short, t));
+                self.emit(&format!("jz 0, {}", end));
+                self.label(&skip);
+                self.emit(&format!("add {}, 0, [rb+{}]", short, t));
+                self.label(&end);
+
+                Ok(Operand::Slot(t))
+            },
+            Expr::Binary(op, l, r) => {
+                let a = self.expr(frame, l)?;
+                let b = self.expr(frame, r)?;
+                self.binary(frame, *op, a, b)
+            },
+            Expr::Call(name, args, line) => self.call(frame, name, args, *line)
+        }
+    }
+
+    fn binary(&mut self, frame: &mut Frame, op: BinOp, a: Operand, b: Operand) -> Result<Operand, CompileError> {
+        if let (Operand::Imm(x), Operand::Imm(y)) = (a, b) {
+            if let Some(v) = fold(op, x, y) {
+                return Ok(Operand::Imm(v));
+            }
+        }
+
+        Ok(match op {
+            BinOp::Add => self.op("add", a, b, frame),
+            BinOp::Mul => self.op("mul", a, b, frame),
+            BinOp::Lt => self.op("lt", a, b, frame),
+            BinOp::Gt => self.op("lt", b, a, frame),
+            BinOp::Eq => self.op("eq", a, b, frame),
+            BinOp::Sub => match b {
+                Operand::Imm(y) => self.op("add", a, Operand::Imm(y.wrapping_neg()), frame),
+                _ => {
+                    let neg = self.op("mul", b, Operand::Imm(-1), frame);
+                    self.op("add", a, neg, frame)
+                }
+            },
+            BinOp::Le => {
+                let gt = self.op("lt", b, a, frame);
+                self.op("eq", gt, Operand::Imm(0), frame)
+            },
+            BinOp::Ge => {
+                let lt = self.op("lt", a, b, frame);
+                self.op("eq", lt, Operand::Imm(0), frame)
+            },
+            BinOp::Ne => {
+                let eq = self.op("eq", a, b, frame);
+                self.op("eq", eq, Operand::Imm(0), frame)
+            },
+            BinOp::Div => return self.call_with(frame, "__div", &[a, b]),
+            BinOp::Mod => return self.call_with(frame, "__mod", &[a, b]),
+            BinOp::And | BinOp::Or => unreachable!("short circuit operators are handled by expr")
+        })
+    }
+
+    /*
+     * Instruction writing a op b to a new temporary
+     */
+    fn op(&mut self, instr: &str, a: Operand, b: Operand, frame: &mut Frame) -> Operand {
+        let t = frame.temp();
+        self.emit(&format!("{} {}, {}, [rb+{}]", instr, a.asm(), b.asm(), t));
+        Operand::Slot(t)
+    }
+
+    fn call(&mut self, frame: &mut Frame, name: &str, args: &[Expr], line: usize) -> Result<Operand, CompileError> {
+        let builtin = match name {
+            "input" => Some(0),
+            "output" => Some(1),
+            _ => None
+        };
+
+        let arity = match builtin.or_else(|| self.arity.get(name).cloned()) {
+            Some(n) => n,
+            None => return Err(self.error(line, format!("unknown function `{}`", name)))
+        };
+
+        if args.len() != arity {
+            return Err(self.error(line, format!("`{}` takes {} arguments, got {}", name, arity, args.len())));
+        }
+
+        // evaluate every argument first so calls among them can't clobber the others
+        let mut vals = Vec::new();
+        for a in args {
+            vals.push(self.expr(frame, a)?);
+        }
+
+        match name {
+            "input" => {
+                let t = frame.temp();
+                self.emit(&format!("in [rb+{}]", t));
+                Ok(Operand::Slot(t))
+            },
+            "output" => {
+                self.emit(&format!("out {}", vals[0].asm()));
+                Ok(Operand::Imm(0))
+            },
+            _ => self.call_with(frame, name, &vals)
+        }
+    }
+
+    /*
+     * Calls a function with arguments already worked out, returning the
+     * temporary its result is copied into
+     */
+    fn call_with(&mut self, frame: &mut Frame, name: &str, args: &[Operand]) -> Result<Operand, CompileError> {
+        let size = format!("fn_{}_frame", frame.name);
+        let ret = self.new_label();
+
+        for (i, a) in args.iter().enumerate() {
+            self.emit(&format!("add {}, 0, [rb+{}+{}]", a.asm(), size, i));
+        }
+
+        self.emit(&format!("add {}, 0, [rb+{}+{}]", ret, size, args.len()));
+        self.emit(&format!("arb {}+{}", size, args.len() + 1));
+        self.emit(&format!("jz 0, fn_{}", name));
+        self.label(&ret);
+        self.emit(&format!("arb -{}-{}", size, args.len() + 1));
+
+        let t = frame.temp();
+        self.emit(&format!("add [__ret], 0, [rb+{}]", t));
+
+        Ok(Operand::Slot(t))
+    }
+
+    fn copy(&mut self, val: Operand, slot: i64) {
+        if val != Operand::Slot(slot) {
+            self.emit(&format!("add {}, 0, {}", val.asm(), Operand::Slot(slot).asm()));
+        }
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn label(&mut self, label: &str) {
+        let _ = writeln!(self.out, "{}:", label);
+    }
+
+    fn emit(&mut self, line: &str) {
+        let _ = writeln!(self.out, "        {}", line);
+    }
+
+    fn error(&self, line: usize, msg: String) -> CompileError {
+        CompileError { source: self.source.to_string(), line, msg }
+    }
+}
+
+
+impl Frame {
+
+    /*
+     * Claims the next free word of the frame
+     */
+    fn temp(&mut self) -> i64 {
+        let slot = self.next;
+        self.next += 1;
+        self.size = self.size.max(self.next);
+        slot
+    }
+
+    fn lookup(&self, name: &str) -> Option<i64> {
+        self.scopes.iter().rev().find_map(|s| s.get(name).cloned())
+    }
+}
+
+
+/*
+ * Value of op on two constants, None where it needs a runtime routine
+ */
+fn fold(op: BinOp, x: i64, y: i64) -> Option<i64> {
+    Some(match op {
+        BinOp::Add => x.wrapping_add(y),
+        BinOp::Sub => x.wrapping_sub(y),
+        BinOp::Mul => x.wrapping_mul(y),
+        BinOp::Lt => (x < y) as i64,
+        BinOp::Le => (x <= y) as i64,
+        BinOp::Gt => (x > y) as i64,
+        BinOp::Ge => (x >= y) as i64,
+        BinOp::Eq => (x == y) as i64,
+        BinOp::Ne => (x != y) as i64,
+        _ => return None
+    })
+}
@@ -0,0 +1,104 @@
+use crate::compiler::CompileError;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tok {
+    Num(i64),
+    Ident(String),
+    Punct(&'static str),
+    End,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub tok: Tok,
+    pub line: usize,
+}
+
+
+// Longest first, so `<=` isn't read as `<` then `=`
+const PUNCT: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||",
+    "(", ")", "{", "}", ",", ";", "=", "<", ">", "+", "-", "*", "/", "%", "!",
+];
+
+
+/*
+ * Splits source into tokens, ending with Tok::End. `//` starts a comment
+ * and 'c' is the number of an ASCII character.
+ */
+pub fn lex(text: &str, source: &str) -> Result<Vec<Token>, CompileError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut rest = text;
+
+    let error = |line, msg: String| CompileError { source: source.to_string(), line, msg };
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\n' {
+            line += 1;
+        }
+
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |i| &rest[i..]);
+            continue;
+        }
+
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest.find(|c: char| ! c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+
+            let tok = if c.is_ascii_digit() {
+                match word.parse() {
+                    Ok(n) => Tok::Num(n),
+                    Err(_) => return Err(error(line, format!("invalid number `{}`", word)))
+                }
+            } else {
+                Tok::Ident(word.to_string())
+            };
+
+            tokens.push(Token { tok, line });
+            continue;
+        }
+
+        if c == '\'' {
+            let mut chars = rest.chars();
+            chars.next();
+
+            let val = match (chars.next(), chars.next(), chars.next()) {
+                (Some('\\'), Some('n'), Some('\'')) => Some(('\n', 4)),
+                (Some('\\'), Some(e), Some('\'')) if e == '\\' || e == '\'' => Some((e, 4)),
+                (Some(v), Some('\''), _) if v != '\\' => Some((v, 2 + v.len_utf8())),
+                _ => None
+            };
+
+            match val {
+                Some((v, len)) => {
+                    tokens.push(Token { tok: Tok::Num(v as i64), line });
+                    rest = &rest[len..];
+                },
+                None => return Err(error(line, "bad character literal".to_string()))
+            };
+            continue;
+        }
+
+        match PUNCT.iter().find(|p| rest.starts_with(*p)) {
+            Some(p) => {
+                tokens.push(Token { tok: Tok::Punct(p), line });
+                rest = &rest[p.len()..];
+            },
+            None => return Err(error(line, format!("unexpected `{}`", c)))
+        };
+    }
+
+    tokens.push(Token { tok: Tok::End, line });
+
+    Ok(tokens)
+}
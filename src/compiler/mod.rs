@@ -0,0 +1,234 @@
+use crate::assembler;
+use std::fmt;
+use std::fs;
+use std::io;
+mod lexer;
+mod parser;
+mod codegen;
+
+
+/*
+ * Division and remainder, which Intcode has no instructions for. Added to
+ * programs that use `/` or `%`. Division truncates towards zero, x / 0 is
+ * 0 and x % 0 is x.
+ */
+const PRELUDE: &str = "
+fn __udiv(a, b) {
+    let q = 0;
+    while a >= b {
+        let p = b;
+        let k = 1;
+        while a - p >= p {
+            p = p + p;
+            k = k + k;
+        }
+        a = a - p;
+        q = q + k;
+    }
+    return q;
+}
+
+fn __div(a, b) {
+    if b == 0 { return 0; }
+    if a < 0 { return -__div(-a, b); }
+    if b < 0 { return -__div(a, -b); }
+    return __udiv(a, b);
+}
+
+fn __mod(a, b) {
+    return a - __div(a, b) * b;
+}
+";
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub source: String,
+    pub line: usize,
+    pub msg: String,
+}
+
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.source, self.line, self.msg)
+    }
+}
+
+
+impl std::error::Error for CompileError {}
+
+
+impl From<CompileError> for io::Error {
+    fn from(err: CompileError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+    }
+}
+
+
+/*
+ * Compiles a program to assembly for the assembler. Programs are a list
+ * of functions, starting from `fn main()`:
+ *
+ *     fn fact(n) {
+ *         if n < 2 { return 1; }
+ *         return n * fact(n - 1);
+ *     }
+ *
+ *     fn main() {
+ *         let n = input();       // reads a value, output(x) writes one
+ *         while n >= 0 {
+ *             output(fact(n));
+ *             n = n - 1;
+ *         }
+ *     }
+ *
+ * Every value is an integer. There are `let` variables scoped to their
+ * block, assignment, if/else, while with break and continue, and return.
+ * Operators, loosest binding first, are || && (both short circuit), == !=,
+ * < <= > >=, + -, * / %, then unary - and !. Comparisons and logic give
+ * 0 or 1 and anything non-zero counts as true. Functions can recurse,
+ * their frames live on a stack at the relative base.
+ */
+pub fn to_asm(text: &str, source: &str) -> Result<String, CompileError> {
+    let tokens = lexer::lex(text, source)?;
+    let mut functions = parser::Parser::new(&tokens, source).program()?;
+
+    let divides = tokens.iter().any(|t| matches!(t.tok, lexer::Tok::Punct("/") | lexer::Tok::Punct("%")));
+    if divides {
+        let tokens = lexer::lex(PRELUDE, "<prelude>")?;
+        functions.extend(parser::Parser::new(&tokens, "<prelude>").program()?);
+    }
+
+    codegen::Codegen::new(source).program(&functions)
+}
+
+
+/*
+ * Compiles a program into words ready for Intcode::new
+ */
+pub fn compile(text: &str, source: &str) -> Result<Vec<i64>, CompileError> {
+    let asm = to_asm(text, source)?;
+
+    match assembler::assemble(&asm, source) {
+        Ok(image) => Ok(image.words),
+        Err(e) => Err(CompileError { source: source.to_string(), line: 0, msg: format!("generated bad assembly: {}", e) })
+    }
+}
+
+
+pub fn compile_file(path: &str) -> Result<Vec<i64>, CompileError> {
+    match fs::read_to_string(path) {
+        Ok(text) => compile(&text, path),
+        Err(e) => Err(CompileError { source: path.to_string(), line: 0, msg: e.to_string() })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Candidate;
+
+    fn run(text: &str, inputs: &[i64]) -> Vec<i64> {
+        let prog = compile(text, "test.c").unwrap();
+        Candidate::inputs(inputs).run(&prog).1
+    }
+
+    fn error(text: &str) -> String {
+        compile(text, "bad.c").unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_recursion() {
+        let text = "
+            fn fact(n) {
+                if n < 2 { return 1; }
+                return n * fact(n - 1);
+            }
+
+            fn fib(n) {
+                if n < 2 { return n; }
+                return fib(n - 1) + fib(n - 2);
+            }
+
+            fn main() {
+                let n = input();
+                while n >= 0 {
+                    output(fact(n));
+                    output(fib(n));
+                    n = n - 1;
+                }
+            }
+        ";
+        assert_eq!(run(text, &[5]), vec!(120,5, 24,3, 6,2, 2,1, 1,1, 1,0));
+        assert_eq!(run(text, &[20])[..2], [2432902008176640000, 6765]);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let text = "
+            fn pick(a, b, c) { return a * 100 + b * 10 + c; }
+
+            fn main() {
+                let x = input();
+                let y = input();
+                output(x - y);
+                output(-x * (y + 1));
+                output(x / y);
+                output(x % y);
+                output(pick(x < y, x >= y, !(x == y) && y != 0 || 0));
+                output(pick(pick(1, 2, 3), x, pick(y, 0, 0)));
+                output(7 / 2 + -7 % 2 * 10 + 'a');
+            }
+        ";
+        assert_eq!(run(text, &[17, 5]), vec!(12, -102, 3, 2, 11, 12970, 90));
+        assert_eq!(run(text, &[-17, 5]), vec!(-22, 102, -3, -2, 101, 12630, 90));
+        assert_eq!(run(text, &[3, 0])[2..5], [0, 3, 10]);
+    }
+
+    #[test]
+    fn test_control() {
+        let text = "
+            // primes below the input, skipping 5, stopping after 11
+            fn main() {
+                let limit = input();
+                let n = 1;
+                while 1 {
+                    n = n + 1;
+                    if n >= limit { break; }
+                    if n == 5 { continue; }
+
+                    let d = 2;
+                    let prime = 1;
+                    while d * d <= n {
+                        if n % d == 0 { prime = 0; }
+                        d = d + 1;
+                    }
+
+                    if !prime {
+                    } else if n > 11 {
+                        return;
+                    } else {
+                        output(n);
+                    }
+                }
+                output(-1);
+            }
+        ";
+        assert_eq!(run(text, &[100]), vec!(2, 3, 7, 11));
+        assert_eq!(run(text, &[9]), vec!(2, 3, 7, -1));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error("fn main() {\n x = 1;\n}"), "bad.c:2: unknown variable `x`");
+        assert_eq!(error("fn main() { f(); }"), "bad.c:1: unknown function `f`");
+        assert_eq!(error("fn main() { output(1, 2); }"), "bad.c:1: `output` takes 1 arguments, got 2");
+        assert_eq!(error("fn main() {\n let x = 1\n}"), "bad.c:3: expected `;`, found `}`");
+        assert_eq!(error("fn f() {}"), "bad.c:1: no `fn main()` to start from");
+        assert_eq!(error("fn main() { break; }"), "bad.c:1: `break` or `continue` outside a loop");
+        assert_eq!(error("fn main() { let if = 2; }"), "bad.c:1: expected a name, found `if`");
+        assert_eq!(error("fn main() { $ }"), "bad.c:1: unexpected `$`");
+    }
+}
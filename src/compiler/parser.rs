@@ -0,0 +1,316 @@
+use crate::compiler::CompileError;
+use crate::compiler::lexer::{Tok, Token};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(i64),
+    Var(String, usize),
+    Call(String, Vec<Expr>, usize),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Let(String, Expr),
+    Assign(String, Expr, usize),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Break(usize),
+    Continue(usize),
+    Expr(Expr),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    pub line: usize,
+}
+
+
+// Binary operators from loosest to tightest binding
+const LEVELS: &[&[(&str, BinOp)]] = &[
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+    &[("<", BinOp::Lt), ("<=", BinOp::Le), (">", BinOp::Gt), (">=", BinOp::Ge)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+];
+
+
+const KEYWORDS: &[&str] = &["fn", "let", "if", "else", "while", "return", "break", "continue"];
+
+
+pub struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    source: &'a str,
+}
+
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [Token], source: &'a str) -> Parser<'a> {
+        Parser { tokens, pos: 0, source }
+    }
+
+    /*
+     * Every function in the source, in order
+     */
+    pub fn program(&mut self) -> Result<Vec<Function>, CompileError> {
+        let mut functions = Vec::new();
+
+        while self.peek() != &Tok::End {
+            functions.push(self.function()?);
+        }
+
+        Ok(functions)
+    }
+
+    fn function(&mut self) -> Result<Function, CompileError> {
+        let line = self.line();
+        self.keyword("fn")?;
+        let name = self.ident()?;
+        self.expect("(")?;
+
+        let mut params = Vec::new();
+        while ! self.eat(")") {
+            if ! params.is_empty() {
+                self.expect(",")?;
+            }
+            params.push(self.ident()?);
+        }
+
+        let body = self.block()?;
+
+        Ok(Function { name, params, body, line })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+
+        let mut stmts = Vec::new();
+        while ! self.eat("}") {
+            stmts.push(self.stmt()?);
+        }
+
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+
+        let stmt = match self.peek().clone() {
+            Tok::Ident(k) if k == "let" => {
+                self.pos += 1;
+                let name = self.ident()?;
+                self.expect("=")?;
+                Stmt::Let(name, self.expr()?)
+            },
+            Tok::Ident(k) if k == "if" => return self.if_stmt(),
+            Tok::Ident(k) if k == "while" => {
+                self.pos += 1;
+                let cond = self.expr()?;
+                return Ok(Stmt::While(cond, self.block()?));
+            },
+            Tok::Ident(k) if k == "return" => {
+                self.pos += 1;
+                match self.peek() {
+                    Tok::Punct(";") => Stmt::Return(None),
+                    _ => Stmt::Return(Some(self.expr()?))
+                }
+            },
+            Tok::Ident(k) if k == "break" => {
+                self.pos += 1;
+                Stmt::Break(line)
+            },
+            Tok::Ident(k) if k == "continue" => {
+                self.pos += 1;
+                Stmt::Continue(line)
+            },
+            Tok::Ident(name) if self.tokens.get(self.pos + 1).map(|t| &t.tok) == Some(&Tok::Punct("=")) => {
+                self.pos += 2;
+                Stmt::Assign(name, self.expr()?, line)
+            },
+            _ => Stmt::Expr(self.expr()?)
+        };
+
+        self.expect(";")?;
+
+        Ok(stmt)
+    }
+
+    fn if_stmt(&mut self) -> Result<Stmt, CompileError> {
+        self.keyword("if")?;
+        let cond = self.expr()?;
+        let then = self.block()?;
+
+        let other = match self.peek() {
+            Tok::Ident(k) if k == "else" => {
+                self.pos += 1;
+                match self.peek() {
+                    Tok::Ident(k) if k == "if" => vec!(self.if_stmt()?),
+                    _ => self.block()?
+                }
+            },
+            _ => Vec::new()
+        };
+
+        Ok(Stmt::If(cond, then, other))
+    }
+
+    pub fn expr(&mut self) -> Result<Expr, CompileError> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+
+        while let Some((_, op)) = LEVELS[level].iter().find(|(p, _)| self.peek() == &Tok::Punct(p)) {
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(*op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat("-") {
+            return Ok(match self.unary()? {
+                Expr::Num(n) => Expr::Num(n.wrapping_neg()),
+                e => Expr::Neg(Box::new(e))
+            });
+        }
+
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let line = self.line();
+
+        match self.peek().clone() {
+            Tok::Num(n) => {
+                self.pos += 1;
+                Ok(Expr::Num(n))
+            },
+            Tok::Punct("(") => {
+                self.pos += 1;
+                let e = self.expr()?;
+                self.expect(")")?;
+                Ok(e)
+            },
+            Tok::Ident(_) => {
+                let name = self.ident()?;
+
+                if ! self.eat("(") {
+                    return Ok(Expr::Var(name, line));
+                }
+
+                let mut args = Vec::new();
+                while ! self.eat(")") {
+                    if ! args.is_empty() {
+                        self.expect(",")?;
+                    }
+                    args.push(self.expr()?);
+                }
+
+                Ok(Expr::Call(name, args, line))
+            },
+            _ => Err(self.unexpected("an expression"))
+        }
+    }
+
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].tok
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].line
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = matches!(self.peek(), Tok::Punct(p) if *p == punct);
+
+        if found {
+            self.pos += 1;
+        }
+
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), CompileError> {
+        match self.eat(punct) {
+            true => Ok(()),
+            false => Err(self.unexpected(&format!("`{}`", punct)))
+        }
+    }
+
+    fn keyword(&mut self, word: &str) -> Result<(), CompileError> {
+        match self.peek() {
+            Tok::Ident(k) if k == word => {
+                self.pos += 1;
+                Ok(())
+            },
+            _ => Err(self.unexpected(&format!("`{}`", word)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        match self.peek().clone() {
+            Tok::Ident(name) if ! KEYWORDS.contains(&name.as_str()) => {
+                self.pos += 1;
+                Ok(name)
+            },
+            _ => Err(self.unexpected("a name"))
+        }
+    }
+
+    fn unexpected(&self, wanted: &str) -> CompileError {
+        let found = match self.peek() {
+            Tok::Num(n) => format!("`{}`", n),
+            Tok::Ident(name) => format!("`{}`", name),
+            Tok::Punct(p) => format!("`{}`", p),
+            Tok::End => "end of file".to_string(),
+        };
+
+        CompileError {
+            source: self.source.to_string(),
+            line: self.line(),
+            msg: format!("expected {}, found {}", wanted, found),
+        }
+    }
+}
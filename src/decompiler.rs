@@ -0,0 +1,695 @@
+use crate::cfg::{Block, Cfg, Exit};
+use crate::disasm::{Instr, Op, Param};
+use crate::intcoder::IntMode;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+// Virtual node every returning block flows into, used for postdominators
+const EXIT: usize = usize::MAX;
+
+
+/*
+ * Condition of a branch, folded from a preceding `lt`/`eq` when possible
+ */
+struct Cond {
+    lhs: String,
+    op: &'static str,
+    rhs: String,
+}
+
+
+impl Cond {
+    fn negate(self) -> Cond {
+        let op = match self.op {
+            "<" => ">=",
+            ">=" => "<",
+            "==" => "!=",
+            _ => "==",
+        };
+
+        Cond { op, ..self }
+    }
+}
+
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.op, self.rhs)
+    }
+}
+
+
+struct Line {
+    depth: usize,
+    text: String,
+    label: Option<usize>,
+}
+
+
+/*
+ * Control flow facts about a single function needed for structuring
+ */
+struct Func<'a> {
+    cfg: &'a Cfg,
+    entry: usize,
+    nodes: BTreeSet<usize>,
+    preds: BTreeMap<usize, Vec<usize>>,
+    dom: BTreeMap<usize, BTreeSet<usize>>,
+    pdom: BTreeMap<usize, BTreeSet<usize>>,
+    loops: BTreeMap<usize, BTreeSet<usize>>,
+    offsets: BTreeMap<usize, Option<i64>>,
+    ret_slot: Option<i64>,
+}
+
+
+impl<'a> Func<'a> {
+    fn new(cfg: &'a Cfg, entry: usize) -> Func<'a> {
+        let nodes = cfg.function(entry);
+        let mut preds: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+
+        for &n in nodes.iter() {
+            for s in cfg.blocks[&n].succs() {
+                preds.entry(s).or_default().push(n);
+            }
+        }
+
+        let offsets = cfg.rel_offsets(entry);
+        let mut ret_slot = None;
+
+        for &n in nodes.iter() {
+            if let Exit::Jump(jump) = &cfg.blocks[&n].exit {
+                if let (IntMode::Rel, Some(o)) = (jump.params[1].mode, Func::block_offset(cfg, &offsets, n)) {
+                    ret_slot = Some(jump.params[1].val + o);
+                }
+            }
+        }
+
+        let mut func = Func {
+            cfg,
+            entry,
+            nodes,
+            preds,
+            dom: BTreeMap::new(),
+            pdom: BTreeMap::new(),
+            loops: BTreeMap::new(),
+            offsets,
+            ret_slot,
+        };
+
+        func.dominators();
+        func.postdominators();
+        func.natural_loops();
+        func
+    }
+
+    /*
+     * Relative base offset at the end of block n, i.e. at its exit jump
+     */
+    fn block_offset(cfg: &Cfg, offsets: &BTreeMap<usize, Option<i64>>, n: usize) -> Option<i64> {
+        let mut offset = offsets.get(&n).cloned().flatten()?;
+
+        for instr in cfg.blocks[&n].instrs.iter().filter(|i| i.op == Op::Rel) {
+            match instr.params[0].mode {
+                IntMode::Imm => offset += instr.params[0].val,
+                _ => return None
+            };
+        }
+
+        Some(offset)
+    }
+
+    fn succs(&self, n: usize) -> Vec<usize> {
+        self.cfg.blocks[&n].succs()
+    }
+
+    fn dominators(&mut self) {
+        let entry = self.entry;
+
+        for &n in self.nodes.iter() {
+            let init = if n == entry { [n].iter().cloned().collect() } else { self.nodes.clone() };
+            self.dom.insert(n, init);
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &n in self.nodes.iter().filter(|n| **n != entry) {
+                let mut set = intersect(self.preds.get(&n).into_iter().flatten().map(|p| &self.dom[p]));
+                set.insert(n);
+
+                if set != self.dom[&n] {
+                    self.dom.insert(n, set);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    fn postdominators(&mut self) {
+        let mut all = self.nodes.clone();
+        all.insert(EXIT);
+
+        for &n in self.nodes.iter() {
+            self.pdom.insert(n, all.clone());
+        }
+        self.pdom.insert(EXIT, [EXIT].iter().cloned().collect());
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &n in self.nodes.iter().rev() {
+                let mut succs = self.succs(n);
+                if succs.is_empty() {
+                    succs.push(EXIT);
+                }
+
+                let mut set = intersect(succs.iter().map(|s| &self.pdom[s]));
+                set.insert(n);
+
+                if set != self.pdom[&n] {
+                    self.pdom.insert(n, set);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /*
+     * Every block targeted by an edge from a block it dominates is a loop
+     * header, the loop body is every block that reaches such an edge
+     * without passing through the header
+     */
+    fn natural_loops(&mut self) {
+        for &n in self.nodes.iter() {
+            for h in self.succs(n) {
+                if ! self.dom[&n].contains(&h) {
+                    continue;
+                }
+
+                let mut body = self.loops.remove(&h).unwrap_or_default();
+                let mut todo = vec!(n);
+                body.insert(h);
+
+                while let Some(b) = todo.pop() {
+                    if body.insert(b) {
+                        todo.extend(self.preds.get(&b).into_iter().flatten());
+                    }
+                }
+
+                self.loops.insert(h, body);
+            }
+        }
+    }
+
+    /*
+     * Closest block every path from n must pass through, None if the paths
+     * from n only meet at the end of the function (or never end)
+     */
+    fn ipdom(&self, n: usize) -> Option<usize> {
+        let pdom = &self.pdom[&n];
+
+        if pdom.len() > self.nodes.len() {
+            return None;
+        }
+
+        pdom.iter()
+            .filter(|p| **p != n)
+            .max_by_key(|p| self.pdom[p].len())
+            .cloned()
+            .filter(|p| *p != EXIT)
+    }
+
+    /*
+     * Block a loop exits to, preferring an exit taken from the header
+     */
+    fn follow(&self, h: usize) -> Option<usize> {
+        let body = &self.loops[&h];
+        let exits: BTreeSet<usize> = body.iter()
+            .flat_map(|n| self.succs(*n))
+            .filter(|s| ! body.contains(s))
+            .collect();
+
+        self.succs(h).into_iter()
+            .find(|s| exits.contains(s))
+            .or_else(|| exits.iter().next().cloned())
+    }
+}
+
+
+fn intersect<'b, I: Iterator<Item = &'b BTreeSet<usize>>>(mut sets: I) -> BTreeSet<usize> {
+    let mut result = match sets.next() {
+        Some(s) => s.clone(),
+        None => return BTreeSet::new()
+    };
+
+    for s in sets {
+        result = result.intersection(s).cloned().collect();
+    }
+
+    result
+}
+
+
+/*
+ * Turns the blocks of one function back into nested pseudocode
+ */
+struct Emitter<'a, 'b> {
+    func: &'b Func<'a>,
+    lines: Vec<Line>,
+    done: BTreeSet<usize>,
+    loops: Vec<(usize, Option<usize>)>,
+    gotos: BTreeSet<usize>,
+    depth: usize,
+}
+
+
+impl<'a, 'b> Emitter<'a, 'b> {
+    fn new(func: &'b Func<'a>) -> Emitter<'a, 'b> {
+        Emitter {
+            func,
+            lines: Vec::new(),
+            done: BTreeSet::new(),
+            loops: Vec::new(),
+            gotos: BTreeSet::new(),
+            depth: 1,
+        }
+    }
+
+    fn line(&mut self, text: String) {
+        self.lines.push(Line { depth: self.depth, text, label: None });
+    }
+
+    fn label(&mut self, addr: usize) {
+        self.lines.push(Line { depth: self.depth, text: format!("L{}:", addr), label: Some(addr) });
+    }
+
+    fn goto(&mut self, addr: usize) {
+        self.gotos.insert(addr);
+        self.line(format!("goto L{};", addr));
+    }
+
+    /*
+     * Emits blocks starting at cur until reaching stop or running out of
+     * places to go
+     */
+    fn seq(&mut self, cur: Option<usize>, stop: Option<usize>) {
+        let mut cur = cur;
+
+        while let Some(b) = cur {
+            if Some(b) == stop {
+                return;
+            }
+
+            if let Some(pos) = self.loops.iter().rposition(|(h, f)| *h == b || *f == Some(b)) {
+                let innermost = pos + 1 == self.loops.len();
+
+                match (innermost, self.loops[pos].0 == b) {
+                    (true, true) => self.line("continue;".to_string()),
+                    (true, false) => self.line("break;".to_string()),
+                    _ => self.goto(b),
+                }
+                return;
+            }
+
+            if self.done.contains(&b) {
+                self.goto(b);
+                return;
+            }
+
+            cur = if self.func.loops.contains_key(&b) {
+                self.emit_loop(b)
+            } else {
+                self.emit_block(b)
+            };
+        }
+    }
+
+    fn emit_loop(&mut self, h: usize) -> Option<usize> {
+        let follow = self.func.follow(h);
+        let block = &self.func.cfg.blocks[&h];
+        let (stmts, cond) = self.render(block);
+        let body = &self.func.loops[&h];
+
+        self.loops.push((h, follow));
+
+        // a header that only tests a condition becomes a while loop
+        let simple = match (&block.exit, cond) {
+            (Exit::Branch { taken: Some(t), fall, .. }, Some(cond)) if stmts.is_empty() => {
+                match (body.contains(t), body.contains(fall)) {
+                    (true, false) if Some(*fall) == follow => Some((cond, *t)),
+                    (false, true) if Some(*t) == follow => Some((cond.negate(), *fall)),
+                    _ => None
+                }
+            },
+            _ => None
+        };
+
+        if let Some((cond, inside)) = simple {
+            self.done.insert(h);
+            self.label(h);
+            self.line(format!("while {} {{", cond));
+            self.depth += 1;
+            self.seq(Some(inside), None);
+        } else {
+            self.line("loop {".to_string());
+            self.depth += 1;
+            let next = self.emit_block(h);
+            self.seq(next, None);
+        }
+
+        if self.lines.last().map(|l| l.text.as_str()) == Some("continue;") {
+            self.lines.pop();
+        }
+
+        self.depth -= 1;
+        self.line("}".to_string());
+        self.loops.pop();
+
+        follow
+    }
+
+    /*
+     * Emits a block's statements and any if/else its exit branch leads to,
+     * returning where execution carries on afterwards
+     */
+    fn emit_block(&mut self, b: usize) -> Option<usize> {
+        let block = &self.func.cfg.blocks[&b];
+        let (stmts, cond) = self.render(block);
+
+        self.done.insert(b);
+        self.label(b);
+        for s in stmts {
+            self.line(s);
+        }
+
+        match &block.exit {
+            Exit::Goto(t) => Some(*t),
+            Exit::Call { target, ret } => {
+                self.line(format!("{}();", name(*target)));
+                Some(*ret)
+            },
+            Exit::Halt => {
+                self.line("halt;".to_string());
+                None
+            },
+            Exit::End => {
+                self.line(format!("// runs into data at {}", self.end(block)));
+                None
+            },
+            Exit::Jump(jump) => {
+                let target = self.operand(&jump.params[1], Func::block_offset(self.func.cfg, &self.func.offsets, b));
+                if target == "ret" {
+                    self.line("return;".to_string());
+                } else {
+                    self.line(format!("goto *{};", target));
+                }
+                None
+            },
+            Exit::Branch { jump, taken: None, fall } => {
+                let offset = Func::block_offset(self.func.cfg, &self.func.offsets, b);
+                let target = self.operand(&jump.params[1], offset);
+                self.line(format!("if {} {{ goto *{}; }}", cond.unwrap(), target));
+                Some(*fall)
+            },
+            Exit::Branch { taken: Some(t), fall, .. } => {
+                let cond = cond.unwrap();
+                let join = self.func.ipdom(b);
+
+                if Some(*fall) == join {
+                    self.arm(format!("if {} {{", cond), *t, join);
+                } else if Some(*t) == join {
+                    self.arm(format!("if {} {{", cond.negate()), *fall, join);
+                } else {
+                    self.arm(format!("if {} {{", cond), *t, join);
+                    self.lines.pop();
+                    self.line("} else {".to_string());
+                    self.depth += 1;
+                    self.seq(Some(*fall), join);
+                    self.depth -= 1;
+                    self.line("}".to_string());
+                }
+
+                join
+            }
+        }
+    }
+
+    fn arm(&mut self, open: String, start: usize, join: Option<usize>) {
+        self.line(open);
+        self.depth += 1;
+        self.seq(Some(start), join);
+        self.depth -= 1;
+        self.line("}".to_string());
+    }
+
+    fn end(&self, block: &Block) -> usize {
+        block.instrs.last().map(|i| i.next()).unwrap_or(block.start)
+    }
+
+    /*
+     * Statements for a block's instructions and the condition of its exit
+     * branch. A comparison feeding straight into the branch is folded into
+     * its condition, and the return address push of a call is dropped.
+     */
+    fn render(&self, block: &Block) -> (Vec<String>, Option<Cond>) {
+        let mut offset = self.func.offsets.get(&block.start).cloned().flatten();
+        let mut instrs: Vec<&Instr> = block.instrs.iter().collect();
+        let mut stmts = Vec::new();
+        let mut cond = None;
+
+        if let Exit::Call { ret, .. } = &block.exit {
+            if let Some(pos) = instrs.iter().rposition(|i| i.constant() == Some(*ret as i64)) {
+                instrs.remove(pos);
+            }
+        }
+
+        if let Exit::Branch { jump, .. } = &block.exit {
+            let tested = &jump.params[0];
+            let folded = match instrs.last() {
+                Some(i) if matches!(i.op, Op::Lt | Op::Eq) && i.params[2] == *tested => Some(*i),
+                _ => None
+            };
+
+            cond = Some(match folded {
+                Some(cmp) => {
+                    instrs.pop();
+                    let end = self.offset_before(block, offset, instrs.len());
+                    Cond {
+                        lhs: self.operand(&cmp.params[0], end),
+                        op: if cmp.op == Op::Lt { "<" } else { "==" },
+                        rhs: self.operand(&cmp.params[1], end),
+                    }
+                },
+                None => Cond {
+                    lhs: self.operand(tested, Func::block_offset(self.func.cfg, &self.func.offsets, block.start)),
+                    op: "!=",
+                    rhs: "0".to_string(),
+                }
+            });
+
+            if jump.op == Op::Jz {
+                cond = cond.map(Cond::negate);
+            }
+        }
+
+        for instr in instrs {
+            let p = |i: usize| self.operand(&instr.params[i], offset);
+
+            match instr.op {
+                Op::Add if p(0) == "0" => stmts.push(format!("{} = {};", p(2), p(1))),
+                Op::Add if p(1) == "0" => stmts.push(format!("{} = {};", p(2), p(0))),
+                Op::Add => stmts.push(format!("{} = {} + {};", p(2), p(0), p(1))),
+                Op::Mul if p(0) == "1" => stmts.push(format!("{} = {};", p(2), p(1))),
+                Op::Mul if p(1) == "1" => stmts.push(format!("{} = {};", p(2), p(0))),
+                Op::Mul => stmts.push(format!("{} = {} * {};", p(2), p(0), p(1))),
+                Op::Lt => stmts.push(format!("{} = {} < {};", p(2), p(0), p(1))),
+                Op::Eq => stmts.push(format!("{} = {} == {};", p(2), p(0), p(1))),
+                Op::In => stmts.push(format!("{} = input();", p(0))),
+                Op::Out => stmts.push(format!("output({});", p(0))),
+                Op::Rel => {
+                    offset = match (offset, instr.params[0].mode) {
+                        (Some(o), IntMode::Imm) => Some(o + instr.params[0].val),
+                        _ => {
+                            stmts.push(format!("rb += {};", p(0)));
+                            None
+                        }
+                    };
+                },
+                Op::Jnz | Op::Jz | Op::Halt => {}
+            };
+        }
+
+        (stmts, cond)
+    }
+
+    /*
+     * Relative base offset before the nth instruction of a block
+     */
+    fn offset_before(&self, block: &Block, start: Option<i64>, n: usize) -> Option<i64> {
+        let mut offset = start;
+
+        for instr in block.instrs[..n].iter().filter(|i| i.op == Op::Rel) {
+            offset = match (offset, instr.params[0].mode) {
+                (Some(o), IntMode::Imm) => Some(o + instr.params[0].val),
+                _ => None
+            };
+        }
+
+        offset
+    }
+
+    /*
+     * Name for a parameter. Relative slots are named by their offset from
+     * the relative base on function entry: the slot a function returns
+     * through is `ret`, others are `local<n>` or, below the entry base,
+     * `outer<n>`. In main the relative base starts at 0 so slots are
+     * plain memory.
+     */
+    fn operand(&self, param: &Param, offset: Option<i64>) -> String {
+        match (param.mode, offset) {
+            (IntMode::Imm, _) => param.val.to_string(),
+            (IntMode::Pos, _) => format!("mem[{}]", param.val),
+            (IntMode::Rel, None) => format!("{}", param),
+            (IntMode::Rel, Some(o)) => {
+                let slot = param.val + o;
+
+                if self.func.entry == 0 {
+                    format!("mem[{}]", slot)
+                } else if Some(slot) == self.func.ret_slot {
+                    "ret".to_string()
+                } else if slot >= 0 {
+                    format!("local{}", slot)
+                } else {
+                    format!("outer{}", -slot)
+                }
+            }
+        }
+    }
+}
+
+
+fn name(entry: usize) -> String {
+    if entry == 0 {
+        "main".to_string()
+    } else {
+        format!("f{}", entry)
+    }
+}
+
+
+/*
+ * Decompiles a program into structured pseudocode. Functions are found from
+ * calls (see Cfg::new), and each is printed with its conditional jumps
+ * turned back into if/else, while and loop blocks. Anything that can't be
+ * structured falls back to labels and gotos.
+ */
+pub fn decompile(prog: &[i64]) -> String {
+    let cfg = Cfg::new(prog);
+    let mut entries = vec!(0);
+    let mut out = String::new();
+
+    entries.extend(cfg.functions.iter().filter(|f| **f != 0));
+
+    for entry in entries {
+        if ! cfg.blocks.contains_key(&entry) {
+            continue;
+        }
+
+        let func = Func::new(&cfg, entry);
+        let mut emitter = Emitter::new(&func);
+        emitter.seq(Some(entry), None);
+
+        if ! out.is_empty() {
+            out.push('\n');
+        }
+
+        out.push_str(&format!("fn {}() {{\n", name(entry)));
+        for line in emitter.lines.iter() {
+            if let Some(addr) = line.label {
+                if ! emitter.gotos.contains(&addr) {
+                    continue;
+                }
+            }
+
+            out.push_str(&format!("{}{}\n", "    ".repeat(line.depth), line.text));
+        }
+        out.push_str("}\n");
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_if_else() {
+        //             0 1 2    3 4 5   6 7  8   9 10
+        let jmp = vec!(3,3,1105,0,8,104,1,99,104,0,99);
+        let expected = "\
+fn main() {
+    mem[3] = input();
+    if 0 != 0 {
+        output(0);
+        halt;
+    } else {
+        output(1);
+        halt;
+    }
+}
+";
+        assert_eq!(decompile(&jmp), expected);
+    }
+
+    #[test]
+    fn test_loop() {
+        // count mem[100] up to 10, outputting each value
+        let prog = vec!(1007,100,10,101,
+                        1006,101,16,
+                        4,100,
+                        1001,100,1,100,
+                        1105,1,0,
+                        99);
+        let expected = "\
+fn main() {
+    while mem[100] < 10 {
+        output(mem[100]);
+        mem[100] = mem[100] + 1;
+    }
+    halt;
+}
+";
+        assert_eq!(decompile(&prog), expected);
+    }
+
+    #[test]
+    fn test_function() {
+        let prog = vec!(109,100,21101,0,9,0,1105,1,12,
+                        104,1,99,
+                        109,1,22101,0,-2,0,204,0,109,-1,2106,0,0);
+        let out = decompile(&prog);
+        assert!(out.contains("fn main() {\n    f12();\n    output(1);\n    halt;\n}\n"), "{}", out);
+        assert!(out.contains("fn f12() {\n    local1 = outer1;\n    output(local1);\n    return;\n}\n"), "{}", out);
+    }
+
+    #[test]
+    fn test_fall_into_data() {
+        // falls through into 42 at 3 when mem[7] is zero
+        let prog = vec!(1005,7,4,42,104,1,99,0);
+        let expected = "\
+fn main() {
+    if mem[7] != 0 {
+        output(1);
+        halt;
+    } else {
+        // runs into data at 3
+    }
+}
+";
+        assert_eq!(decompile(&prog), expected);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::intcoder::Device;
+use crate::rng::Rng;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+
+/*
+ * Character console, one word wide at offset 0 with a status word at 1:
+ *
+ * write 0 -> append the value as an ASCII character to the output
+ * read 0  -> next queued input character, -1 if there is none
+ * read 1  -> number of queued input characters
+ */
+pub struct Console {
+    pub output: String,
+    pub input: VecDeque<i64>,
+}
+
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            output: String::new(),
+            input: VecDeque::new(),
+        }
+    }
+
+    /*
+     * Queue text for the program to read
+     */
+    pub fn type_in(&mut self, text: &str) {
+        self.input.extend(text.bytes().map(|b| b as i64));
+    }
+}
+
+
+impl Default for Console {
+    fn default() -> Console {
+        Console::new()
+    }
+}
+
+
+impl Device for Console {
+    fn read(&mut self, offset: usize) -> i64 {
+        match offset {
+            0 => self.input.pop_front().unwrap_or(-1),
+            _ => self.input.len() as i64
+        }
+    }
+
+    fn write(&mut self, offset: usize, val: i64) {
+        if offset == 0 {
+            self.output.push(val as u8 as char);
+        }
+    }
+}
+
+
+/*
+ * Grid of pixel values laid out row by row, so the pixel at (x, y) is at
+ * offset y * width + x. Map it with a length of width * height.
+ */
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<i64>,
+}
+
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec!(0; width * height),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> i64 {
+        self.pixels[y * self.width + x]
+    }
+
+    /*
+     * Text picture of the buffer, blank for 0 and '#' for anything else
+     */
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for row in self.pixels.chunks(self.width) {
+            for p in row {
+                out.push(if *p == 0 { ' ' } else { '#' });
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: usize) -> i64 {
+        self.pixels[offset]
+    }
+
+    fn write(&mut self, offset: usize, val: i64) {
+        self.pixels[offset] = val;
+    }
+}
+
+
+/*
+ * Source of random numbers, every read gives a new non-negative value and
+ * writing a value reseeds it
+ */
+pub struct Random {
+    rng: Rng,
+}
+
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { rng: Rng::new(seed) }
+    }
+}
+
+
+impl Device for Random {
+    fn read(&mut self, _offset: usize) -> i64 {
+        (self.rng.bits() >> 1) as i64
+    }
+
+    fn write(&mut self, _offset: usize, val: i64) {
+        self.rng = Rng::new(val as u64);
+    }
+}
+
+
+/*
+ * Milliseconds since the clock was created or last written to
+ */
+pub struct Clock {
+    start: Instant,
+}
+
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock { start: Instant::now() }
+    }
+}
+
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new()
+    }
+}
+
+
+impl Device for Clock {
+    fn read(&mut self, _offset: usize) -> i64 {
+        self.start.elapsed().as_millis() as i64
+    }
+
+    fn write(&mut self, _offset: usize, _val: i64) {
+        self.start = Instant::now();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcoder::{Intcode, IntResponse};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_console() {
+        // echo console input back until it runs dry, then write '!'
+        let prog = vec!(1001,100,0,50,
+                        1007,50,0,51,
+                        1005,51,19,
+                        1001,50,0,100,
+                        1105,1,0,
+                        0,
+                        1101,33,0,100,
+                        99);
+        let console = Arc::new(Mutex::new(Console::new()));
+        console.lock().unwrap().type_in("hi");
+
+        let mut icoder = Intcode::new(&prog);
+        assert!(icoder.map(100, 2, console.clone()));
+        assert!(matches!(icoder.run(), IntResponse::Halt));
+        assert_eq!(console.lock().unwrap().output, "hi!");
+    }
+
+    #[test]
+    fn test_framebuffer() {
+        // draw a diagonal on a 3x3 screen at 200, then read a pixel back
+        let prog = vec!(1101,0,1,200,1101,0,1,204,1101,0,1,208,4,204,4,201,99);
+        let screen = Arc::new(Mutex::new(Framebuffer::new(3, 3)));
+
+        let mut icoder = Intcode::new(&prog);
+        icoder.map(200, 9, screen.clone());
+        assert!(matches!(icoder.run(), IntResponse::Output(1)));
+        assert!(matches!(icoder.run(), IntResponse::Output(0)));
+        assert_eq!(screen.lock().unwrap().render(), "#  \n # \n  #\n");
+        assert_eq!(screen.lock().unwrap().get(1, 1), 1);
+    }
+
+    #[test]
+    fn test_several() {
+        let prog = vec!(4,300,4,300,4,301,99);
+        let mut icoder = Intcode::new(&prog);
+        assert!(icoder.map(300, 1, Random::new(1)));
+        assert!(icoder.map(301, 1, Clock::new()));
+        assert!(! icoder.map(290, 11, Console::new()));
+
+        let first = match icoder.run() { IntResponse::Output(i) => i, _ => -1 };
+        let second = match icoder.run() { IntResponse::Output(i) => i, _ => -1 };
+        assert!(first >= 0 && second >= 0 && first != second);
+        assert!(matches!(icoder.run(), IntResponse::Output(t) if t >= 0));
+    }
+
+    #[test]
+    fn test_thread() {
+        // a machine with a device mapped can still run on another thread
+        let prog = vec!(1101,72,0,100,99);
+        let console = Arc::new(Mutex::new(Console::new()));
+
+        let mut icoder = Intcode::new(&prog);
+        icoder.map(100, 2, console.clone());
+        let response = std::thread::spawn(move || icoder.run()).join().unwrap();
+        assert!(matches!(response, IntResponse::Halt));
+        assert_eq!(console.lock().unwrap().output, "H");
+    }
+}
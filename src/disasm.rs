@@ -0,0 +1,373 @@
+use crate::intcoder::IntMode;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Mul,
+    In,
+    Out,
+    Jnz,
+    Jz,
+    Lt,
+    Eq,
+    Rel,
+    Halt,
+}
+
+
+impl Op {
+
+    /*
+     * Opcode for the low two digits of an instruction, None if not an opcode
+     */
+    pub fn new(code: i64) -> Option<Op> {
+        match code {
+            1 => Some(Op::Add),
+            2 => Some(Op::Mul),
+            3 => Some(Op::In),
+            4 => Some(Op::Out),
+            5 => Some(Op::Jnz),
+            6 => Some(Op::Jz),
+            7 => Some(Op::Lt),
+            8 => Some(Op::Eq),
+            9 => Some(Op::Rel),
+            99 => Some(Op::Halt),
+            _ => None
+        }
+    }
+
+    pub fn code(&self) -> i64 {
+        match self {
+            Op::Add => 1,
+            Op::Mul => 2,
+            Op::In => 3,
+            Op::Out => 4,
+            Op::Jnz => 5,
+            Op::Jz => 6,
+            Op::Lt => 7,
+            Op::Eq => 8,
+            Op::Rel => 9,
+            Op::Halt => 99,
+        }
+    }
+
+    /*
+     * Number of parameters following the opcode
+     */
+    pub fn arity(&self) -> usize {
+        match self {
+            Op::Add | Op::Mul | Op::Lt | Op::Eq => 3,
+            Op::Jnz | Op::Jz => 2,
+            Op::In | Op::Out | Op::Rel => 1,
+            Op::Halt => 0,
+        }
+    }
+
+    /*
+     * Index of the parameter the instruction writes to, if any
+     */
+    pub fn writes(&self) -> Option<usize> {
+        match self {
+            Op::Add | Op::Mul | Op::Lt | Op::Eq => Some(2),
+            Op::In => Some(0),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Add => "add",
+            Op::Mul => "mul",
+            Op::In => "in",
+            Op::Out => "out",
+            Op::Jnz => "jnz",
+            Op::Jz => "jz",
+            Op::Lt => "lt",
+            Op::Eq => "eq",
+            Op::Rel => "arb",
+            Op::Halt => "halt",
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Param {
+    pub mode: IntMode,
+    pub val: i64,
+}
+
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            IntMode::Pos => write!(f, "[{}]", self.val),
+            IntMode::Imm => write!(f, "{}", self.val),
+            IntMode::Rel if self.val < 0 => write!(f, "[rb-{}]", -self.val),
+            IntMode::Rel => write!(f, "[rb+{}]", self.val),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instr {
+    pub addr: usize,
+    pub op: Op,
+    pub params: Vec<Param>,
+}
+
+
+impl Instr {
+
+    /*
+     * Decodes the instruction at addr. Returns None when the word there isn't
+     * a valid opcode, has bad parameter modes, writes in immediate mode or
+     * runs off the end of the program.
+     */
+    pub fn decode(prog: &[i64], addr: usize) -> Option<Instr> {
+        let instruction = *prog.get(addr)?;
+
+        if instruction < 0 {
+            return None;
+        }
+
+        let op = Op::new(instruction % 100)?;
+        let mut modes = instruction / 100;
+        let mut params = Vec::new();
+
+        for i in 0..op.arity() {
+            let mode = IntMode::new(modes % 10)?;
+            modes /= 10;
+
+            if mode == IntMode::Imm && op.writes() == Some(i) {
+                return None;
+            }
+
+            params.push(Param { mode, val: *prog.get(addr + 1 + i)? });
+        }
+
+        if modes != 0 {
+            return None;
+        }
+
+        Some(Instr { addr, op, params })
+    }
+
+    /*
+     * Words the instruction assembles back to, the reverse of decode
+     */
+    pub fn encode(&self) -> Vec<i64> {
+        let mut code = self.op.code();
+        let mut scale = 100;
+
+        for p in &self.params {
+            code += scale * match p.mode {
+                IntMode::Pos => 0,
+                IntMode::Imm => 1,
+                IntMode::Rel => 2,
+            };
+            scale *= 10;
+        }
+
+        let mut words = vec!(code);
+        words.extend(self.params.iter().map(|p| p.val));
+        words
+    }
+
+    /*
+     * Number of words the instruction takes up, opcode included
+     */
+    pub fn size(&self) -> usize {
+        1 + self.params.len()
+    }
+
+    pub fn next(&self) -> usize {
+        self.addr + self.size()
+    }
+
+    /*
+     * Jump target if it is known statically, i.e. given in immediate mode
+     */
+    pub fn target(&self) -> Option<usize> {
+        match self.op {
+            Op::Jnz | Op::Jz => match self.params[1] {
+                Param { mode: IntMode::Imm, val } if val >= 0 => Some(val as usize),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    /*
+     * True if the instruction is a jump whose condition is a constant that
+     * always takes it, e.g. `jnz 1, 12` or `jz 0, 12`
+     */
+    pub fn always_jumps(&self) -> bool {
+        match (self.op, self.params.first()) {
+            (Op::Jnz, Some(Param { mode: IntMode::Imm, val })) => *val != 0,
+            (Op::Jz, Some(Param { mode: IntMode::Imm, val })) => *val == 0,
+            _ => false
+        }
+    }
+
+    pub fn is_jump(&self) -> bool {
+        matches!(self.op, Op::Jnz | Op::Jz)
+    }
+
+    /*
+     * Value the instruction writes if both its inputs are immediate
+     */
+    pub fn constant(&self) -> Option<i64> {
+        let (a, b) = match self.params.as_slice() {
+            [Param { mode: IntMode::Imm, val: a }, Param { mode: IntMode::Imm, val: b }, _] => (*a, *b),
+            _ => return None
+        };
+
+        match self.op {
+            Op::Add => Some(a + b),
+            Op::Mul => Some(a * b),
+            Op::Lt => Some((a < b) as i64),
+            Op::Eq => Some((a == b) as i64),
+            _ => None
+        }
+    }
+}
+
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.op.name())?;
+
+        for (i, p) in self.params.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", sep, p)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/*
+ * Disassembles every instruction reachable from address 0 by following
+ * fallthrough and immediate jump targets. Words never reached that way are
+ * treated as data. Indirect jumps (target read from memory) end a path, but
+ * if an unconditional jump is preceded by a constant write of the address
+ * right after it, that address is followed as well, since that is how
+ * compiled programs push a return address before a call.
+ */
+pub fn disassemble(prog: &[i64]) -> BTreeMap<usize, Instr> {
+    let mut instrs = BTreeMap::new();
+    let mut todo = vec!(0);
+
+    while let Some(addr) = todo.pop() {
+        let mut addr = addr;
+        let mut consts = Vec::new();
+
+        while ! instrs.contains_key(&addr) {
+            let instr = match Instr::decode(prog, addr) {
+                Some(i) => i,
+                None => break
+            };
+
+            if let Some(t) = instr.target() {
+                todo.push(t);
+            }
+
+            if instr.always_jumps() && consts.contains(&(instr.next() as i64)) {
+                todo.push(instr.next());
+            }
+
+            if let Some(c) = instr.constant() {
+                consts.push(c);
+            }
+
+            let stop = instr.op == Op::Halt || instr.always_jumps();
+
+            addr = instr.next();
+            instrs.insert(instr.addr, instr);
+
+            if stop {
+                break;
+            }
+        }
+    }
+
+    instrs
+}
+
+
+/*
+ * Text listing of a program, one instruction per line with addresses and
+ * anything not reached by disassemble printed as data words
+ */
+pub fn listing(prog: &[i64]) -> String {
+    let instrs = disassemble(prog);
+    let mut covered = BTreeSet::new();
+    let mut out = String::new();
+
+    for instr in instrs.values() {
+        covered.extend(instr.addr..instr.next());
+    }
+
+    let mut addr = 0;
+    while addr < prog.len() {
+        if let Some(instr) = instrs.get(&addr) {
+            out.push_str(&format!("{:>6}: {}\n", addr, instr));
+            addr = instr.next();
+        } else {
+            if ! covered.contains(&addr) {
+                out.push_str(&format!("{:>6}: .data {}\n", addr, prog[addr]));
+            }
+            addr += 1;
+        }
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let prog = vec!(1002,4,3,4,33);
+        let instr = Instr::decode(&prog, 0).unwrap();
+        assert_eq!(instr.op, Op::Mul);
+        assert_eq!(instr.size(), 4);
+        assert_eq!(format!("{}", instr), "mul [4], 3, [4]");
+
+        let prog = vec!(21101,1,-1,-3);
+        let instr = Instr::decode(&prog, 0).unwrap();
+        assert_eq!(format!("{}", instr), "add 1, -1, [rb-3]");
+        assert_eq!(instr.encode(), prog);
+
+        // immediate write, bad mode, bad opcode, truncated
+        assert!(Instr::decode(&[11101,1,1,1], 0).is_none());
+        assert!(Instr::decode(&[301,1,1,1], 0).is_none());
+        assert!(Instr::decode(&[42], 0).is_none());
+        assert!(Instr::decode(&[1,1,1], 0).is_none());
+    }
+
+    #[test]
+    fn test_disassemble() {
+        //             0 1 2    3 4 5   6 7  8   9 10
+        let jmp = vec!(3,3,1105,0,8,104,1,99,104,0,99);
+        let instrs = disassemble(&jmp);
+        let addrs: Vec<usize> = instrs.keys().cloned().collect();
+        assert_eq!(addrs, vec!(0,2,5,7,8,10));
+    }
+
+    #[test]
+    fn test_listing() {
+        let prog = vec!(1105,1,4,7,104,0,99);
+        let out = listing(&prog);
+        assert_eq!(out, "     0: jnz 1, 4\n     3: .data 7\n     4: out 0\n     6: halt\n");
+    }
+}
@@ -26,10 +26,7 @@ impl Space {
         let mut not_wall = false;
 
         if let Some(tile) = &self.tile {
-            not_wall = match tile {
-                Tile::Wall => false,
-                _ => true
-            }
+            not_wall = ! matches!(tile, Tile::Wall)
         }
 
         match self.tile {
@@ -82,7 +79,7 @@ impl Map {
         let (x, y) = dir.go(self.curr);
 
         if self.map.len() > x && self.map.len() > y {
-            self.map[y][x].tile = Some(tile.clone());
+            self.map[y][x].tile = Some(*tile);
         }
     }
 
@@ -95,7 +92,7 @@ impl Map {
                     print!("{}", t.char());
                 }
             }
-            println!("");
+            println!();
         }
     }
 
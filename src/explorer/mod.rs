@@ -10,7 +10,7 @@ pub struct Explorer<'a> {
 }
 
 impl<'a> Explorer<'a> {
-    pub fn new(icoder : &mut Intcode) -> Explorer {
+    pub fn new(icoder : &mut Intcode) -> Explorer<'_> {
         Explorer {
             robot: robot::Robot::new(icoder),
             map: map::Map::new(50),
@@ -41,18 +41,15 @@ impl<'a> Explorer<'a> {
                 self.map.go(dir);
                 path.push(dir);
 
-                match tile {
-                    tile::Tile::Oxy => {
-                        if oxy_trace && ! has_reset {
-                            self.map.reset();
-                            path = Vec::new();
-                            longest = 0;
-                            has_reset = true;
-                        } else if ! oxy_trace {
-                            return path.len();
-                        }
-                    },
-                    _ => {}
+                if let tile::Tile::Oxy = tile {
+                    if oxy_trace && ! has_reset {
+                        self.map.reset();
+                        path = Vec::new();
+                        longest = 0;
+                        has_reset = true;
+                    } else if ! oxy_trace {
+                        return path.len();
+                    }
                 };
 
             // headback otherwise
@@ -7,7 +7,7 @@ pub struct Robot<'a> {
 }
 
 impl<'a> Robot<'a> {
-    pub fn new(icoder : &'a mut Intcode) -> Robot {
+    pub fn new(icoder : &'a mut Intcode) -> Robot<'a> {
         Robot {
             icoder,
         }
@@ -38,7 +38,7 @@ impl<'a> Robot<'a> {
                 _ => panic!("invalid tile response!")
             };
 
-            tiles[(i-1) as usize] = tile.clone();
+            tiles[(i-1) as usize] = tile;
         }
 
         tiles
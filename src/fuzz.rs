@@ -0,0 +1,454 @@
+use crate::intcoder::{Engine, IntResponse};
+use crate::rng::Rng;
+use std::panic::{self, AssertUnwindSafe};
+
+// Generated programs keep their data here, well past the end of the code
+// but inside the zeroed padding every engine is expected to provide
+const DATA: i64 = 2000;
+const DATA_SIZE: i64 = 64;
+
+// The first few data cells are reserved for loop counters
+const COUNTERS: i64 = 4;
+
+// Upper bound on events recorded per run, in case an engine never stops
+const MAX_EVENTS: usize = 10000;
+
+// Instructions an engine may run between events, for loops that never do I/O
+const MAX_STEPS: u64 = 1_000_000;
+
+
+/*
+ * A piece of a generated program. Loops and skips contain other segments
+ * and are assembled with their jumps once the final addresses are known.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Instr(Vec<i64>),
+    Loop { counter: i64, times: i64, body: Vec<Segment> },
+    Skip { jz: bool, test: i64, body: Vec<Segment> },
+}
+
+
+/*
+ * Random program made of straight-line instructions, bounded loops and
+ * forward conditional jumps. Every program halts: loops count a reserved
+ * cell down from a small constant and nothing else writes to it.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub segments: Vec<Segment>,
+}
+
+
+impl Program {
+
+    pub fn generate(rng: &mut Rng, size: usize) -> Program {
+        let mut gen = Generator { rng, rel: DATA };
+        let mut segments = vec!(Segment::Instr(vec!(109, DATA)));
+
+        segments.extend(gen.segments(size, 0));
+
+        Program { segments }
+    }
+
+    /*
+     * Lays out the segments from address 0 and appends a halt
+     */
+    pub fn assemble(&self) -> Vec<i64> {
+        let mut prog = Vec::new();
+        assemble(&self.segments, &mut prog);
+        prog.push(99);
+        prog
+    }
+
+    /*
+     * Number of segments, counting those nested in loops and skips
+     */
+    pub fn size(&self) -> usize {
+        fn count(segments: &[Segment]) -> usize {
+            segments.iter().map(|s| match s {
+                Segment::Instr(_) => 1,
+                Segment::Loop { body, .. } | Segment::Skip { body, .. } => 1 + count(body),
+            }).sum()
+        }
+
+        count(&self.segments)
+    }
+
+    /*
+     * Copy of the program with the nth segment (in the order size counts
+     * them) removed
+     */
+    fn without(&self, n: usize) -> Program {
+        fn remove(segments: &mut Vec<Segment>, n: &mut usize) -> bool {
+            for i in 0..segments.len() {
+                if *n == 0 {
+                    segments.remove(i);
+                    return true;
+                }
+                *n -= 1;
+
+                if let Segment::Loop { body, .. } | Segment::Skip { body, .. } = &mut segments[i] {
+                    if remove(body, n) {
+                        return true;
+                    }
+                }
+            }
+
+            false
+        }
+
+        let mut prog = self.clone();
+        let mut n = n;
+        remove(&mut prog.segments, &mut n);
+        prog
+    }
+}
+
+
+fn assemble(segments: &[Segment], prog: &mut Vec<i64>) {
+    for s in segments {
+        match s {
+            Segment::Instr(words) => prog.extend(words),
+            Segment::Loop { counter, times, body } => {
+                prog.extend(&[1101, *times, 0, *counter]);
+                let start = prog.len() as i64;
+                assemble(body, prog);
+                prog.extend(&[1001, *counter, -1, *counter]);
+                prog.extend(&[1005, *counter, start]);
+            },
+            Segment::Skip { jz, test, body } => {
+                prog.extend(&[if *jz { 1006 } else { 1005 }, *test, 0]);
+                let patch = prog.len() - 1;
+                assemble(body, prog);
+                prog[patch] = prog.len() as i64;
+            }
+        }
+    }
+}
+
+
+struct Generator<'a> {
+    rng: &'a mut Rng,
+    rel: i64,
+}
+
+
+impl<'a> Generator<'a> {
+
+    /*
+     * Random segments, the relative base only moves at the top level so
+     * its value is known everywhere
+     */
+    fn segments(&mut self, count: usize, depth: i64) -> Vec<Segment> {
+        let mut segments = Vec::new();
+
+        for _ in 0..count {
+            let kind = self.rng.range(0, 9);
+
+            segments.push(match kind {
+                0 if depth < COUNTERS - 1 => {
+                    let len = self.rng.range(1, 4) as usize;
+                    let body = self.segments(len, depth + 1);
+                    Segment::Loop { counter: DATA + depth, times: self.rng.range(1, 4), body }
+                },
+                1 if depth < COUNTERS - 1 => {
+                    let test = self.cell(false);
+                    let len = self.rng.range(1, 4) as usize;
+                    let body = self.segments(len, depth + 1);
+                    Segment::Skip { jz: self.rng.chance(50), test, body }
+                },
+                2 if depth == 0 => {
+                    let target = self.rng.range(DATA, DATA + DATA_SIZE - 1);
+                    let step = target - self.rel;
+                    self.rel = target;
+                    Segment::Instr(vec!(109, step))
+                },
+                3 => {
+                    let (mode, dst) = self.write();
+                    Segment::Instr(vec!(3 + 100 * mode, dst))
+                },
+                4 => {
+                    let (mode, src) = self.read();
+                    Segment::Instr(vec!(4 + 100 * mode, src))
+                },
+                _ => {
+                    let code = [1, 1, 2, 7, 8][self.rng.range(0, 4) as usize];
+                    let (m1, a) = self.read();
+                    let (m2, b) = self.read();
+                    let (m3, c) = self.write();
+
+                    // keep products small enough not to overflow
+                    let (m2, b) = if code == 2 { (1, self.rng.range(-3, 3)) } else { (m2, b) };
+
+                    Segment::Instr(vec!(code + 100 * m1 + 1000 * m2 + 10000 * m3, a, b, c))
+                }
+            });
+        }
+
+        segments
+    }
+
+    /*
+     * Address of a data cell, counters are only handed out for reading
+     */
+    fn cell(&mut self, write: bool) -> i64 {
+        let lo = if write { DATA + COUNTERS } else { DATA };
+        self.rng.range(lo, DATA + DATA_SIZE - 1)
+    }
+
+    /*
+     * Mode and value for a parameter that is read from
+     */
+    fn read(&mut self) -> (i64, i64) {
+        match self.rng.range(0, 2) {
+            0 => (0, self.cell(false)),
+            1 => (1, self.rng.range(-50, 50)),
+            _ => (2, self.cell(false) - self.rel),
+        }
+    }
+
+    /*
+     * Mode and value for a parameter that is written to
+     */
+    fn write(&mut self) -> (i64, i64) {
+        if self.rng.chance(50) {
+            (0, self.cell(true))
+        } else {
+            (2, self.cell(true) - self.rel)
+        }
+    }
+}
+
+
+/*
+ * Something observable an engine did while running a program
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Output(i64),
+    Input(i64),
+    Halt,
+    Starved,
+    Timeout,
+    Panic(String),
+}
+
+
+/*
+ * Runs prog on an engine, feeding it inputs in order, and records what it
+ * did until it halts, asks for more input than given, runs too long
+ * without doing either, or panics
+ */
+pub fn trace<E, F>(engine: &F, prog: &[i64], inputs: &[i64]) -> Vec<Event>
+    where E: Engine, F: Fn(&[i64]) -> E {
+    let mut machine = engine(prog);
+    let mut inputs = inputs.iter();
+    let mut events = Vec::new();
+
+    while events.len() < MAX_EVENTS {
+        let response = panic::catch_unwind(AssertUnwindSafe(|| machine.run_for(MAX_STEPS)));
+
+        match response {
+            Ok(Some(IntResponse::Output(i))) => events.push(Event::Output(i)),
+            Ok(Some(IntResponse::Input)) => match inputs.next() {
+                Some(i) => {
+                    machine.input(*i);
+                    events.push(Event::Input(*i));
+                },
+                None => {
+                    events.push(Event::Starved);
+                    break;
+                }
+            },
+            Ok(Some(IntResponse::Halt)) => {
+                events.push(Event::Halt);
+                break;
+            },
+            Ok(None) => {
+                events.push(Event::Timeout);
+                break;
+            },
+            Err(e) => {
+                let msg = e.downcast_ref::<String>().cloned()
+                    .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                    .unwrap_or_default();
+                events.push(Event::Panic(msg));
+                break;
+            }
+        }
+    }
+
+    events
+}
+
+
+/*
+ * First point where two engines disagreed on the same program and inputs
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub prog: Vec<i64>,
+    pub inputs: Vec<i64>,
+    pub step: usize,
+    pub left: Option<Event>,
+    pub right: Option<Event>,
+}
+
+
+/*
+ * Runs prog on both engines and returns the first event they differ on
+ */
+pub fn compare<A, B, FA, FB>(left: &FA, right: &FB, prog: &[i64], inputs: &[i64]) -> Option<Divergence>
+    where A: Engine, B: Engine, FA: Fn(&[i64]) -> A, FB: Fn(&[i64]) -> B {
+    let a = trace(left, prog, inputs);
+    let b = trace(right, prog, inputs);
+
+    (0..a.len().max(b.len()))
+        .find(|i| a.get(*i) != b.get(*i))
+        .map(|step| Divergence {
+            prog: prog.to_vec(),
+            inputs: inputs.to_vec(),
+            step,
+            left: a.get(step).cloned(),
+            right: b.get(step).cloned(),
+        })
+}
+
+
+/*
+ * Generates cases random programs from seed and compares both engines on
+ * each. The first divergence found is minimized by dropping segments and
+ * inputs for as long as the engines still disagree.
+ */
+pub fn fuzz<A, B, FA, FB>(left: &FA, right: &FB, seed: u64, cases: usize) -> Option<Divergence>
+    where A: Engine, B: Engine, FA: Fn(&[i64]) -> A, FB: Fn(&[i64]) -> B {
+    let mut rng = Rng::new(seed);
+
+    for _ in 0..cases {
+        let size = rng.range(1, 20) as usize;
+        let prog = Program::generate(&mut rng, size);
+        let inputs: Vec<i64> = (0..32).map(|_| rng.range(-100, 100)).collect();
+
+        if compare(left, right, &prog.assemble(), &inputs).is_some() {
+            return minimize(left, right, prog, inputs);
+        }
+    }
+
+    None
+}
+
+
+fn minimize<A, B, FA, FB>(left: &FA, right: &FB, prog: Program, inputs: Vec<i64>) -> Option<Divergence>
+    where A: Engine, B: Engine, FA: Fn(&[i64]) -> A, FB: Fn(&[i64]) -> B {
+    let mut prog = prog;
+    let mut inputs = inputs;
+    let mut shrunk = true;
+
+    while shrunk {
+        shrunk = false;
+
+        // the relative base setup at the start is always kept
+        for n in (1..prog.size()).rev() {
+            if n >= prog.size() {
+                continue;
+            }
+
+            let smaller = prog.without(n);
+            if compare(left, right, &smaller.assemble(), &inputs).is_some() {
+                prog = smaller;
+                shrunk = true;
+            }
+        }
+
+        while ! inputs.is_empty() {
+            let fewer = &inputs[..inputs.len() - 1];
+            if compare(left, right, &prog.assemble(), fewer).is_none() {
+                break;
+            }
+            inputs.pop();
+            shrunk = true;
+        }
+    }
+
+    compare(left, right, &prog.assemble(), &inputs)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcoder::Intcode;
+
+    /*
+     * Intcode with a bug: outputs of 7 come out as 8
+     */
+    struct Buggy(Intcode);
+
+    impl Engine for Buggy {
+        fn input(&mut self, num: i64) -> bool {
+            self.0.input(num)
+        }
+
+        fn run(&mut self) -> IntResponse {
+            match self.0.run() {
+                IntResponse::Output(7) => IntResponse::Output(8),
+                r => r
+            }
+        }
+
+        fn run_for(&mut self, steps: u64) -> Option<IntResponse> {
+            match self.0.run_for(steps) {
+                Some(IntResponse::Output(7)) => Some(IntResponse::Output(8)),
+                r => r
+            }
+        }
+    }
+
+    #[test]
+    fn test_generated_halt() {
+        let mut rng = Rng::new(1);
+
+        for _ in 0..200 {
+            let prog = Program::generate(&mut rng, 20).assemble();
+            let events = trace(&|p: &[i64]| Intcode::new(p), &prog, &[0; 1000]);
+            assert_eq!(events.last(), Some(&Event::Halt), "{:?}", prog);
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let intcode = |p: &[i64]| Intcode::new(p);
+        assert_eq!(trace(&intcode, &[1105,1,0], &[]), vec!(Event::Timeout));
+    }
+
+    #[test]
+    fn test_same_engine() {
+        let intcode = |p: &[i64]| Intcode::new(p);
+        assert_eq!(fuzz(&intcode, &intcode, 7, 200), None);
+    }
+
+    #[test]
+    fn test_finds_bug() {
+        let intcode = |p: &[i64]| Intcode::new(p);
+        let buggy = |p: &[i64]| Buggy(Intcode::new(p));
+
+        let div = fuzz(&intcode, &buggy, 3, 5000).expect("bug not found");
+        assert_eq!(div.left, Some(Event::Output(7)));
+        assert_eq!(div.right, Some(Event::Output(8)));
+
+        // the reproducer should be down to setup, one write of 7 and its output
+        assert!(div.prog.len() <= 12, "{:?}", div);
+    }
+
+    #[test]
+    fn test_compare() {
+        let intcode = |p: &[i64]| Intcode::new(p);
+        let buggy = |p: &[i64]| Buggy(Intcode::new(p));
+        let prog = vec!(3,9,4,9,104,7,99);
+
+        let div = compare(&intcode, &buggy, &prog, &[1]).unwrap();
+        assert_eq!(div.step, 2);
+        assert_eq!(trace(&intcode, &prog, &[1]), vec!(Event::Input(1), Event::Output(1), Event::Output(7), Event::Halt));
+    }
+}
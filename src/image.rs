@@ -0,0 +1,351 @@
+use crate::intcoder::{Intcode, PADDING};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+/*
+ * Binary program image layout, all integers after the fixed header are
+ * LEB128 varints and program words are zigzag encoded first so small
+ * negative values stay small:
+ *
+ *   magic        4 bytes, "INTC"
+ *   version      1 byte
+ *   word size    1 byte, bytes needed to hold any word (4 or 8)
+ *   flags        1 byte, bit 0 set if a symbol table follows the words
+ *   entry        varint
+ *   word count   varint
+ *   words        zigzag varints
+ *   symbols      varint count, then per symbol a varint name length,
+ *                the UTF-8 name and a varint address
+ */
+pub const MAGIC: [u8; 4] = *b"INTC";
+pub const VERSION: u8 = 1;
+
+const FLAG_SYMBOLS: u8 = 1;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub entry: usize,
+    pub words: Vec<i64>,
+    pub symbols: BTreeMap<String, usize>,
+}
+
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    Magic,
+    Version(u8),
+    WordSize(u8),
+    Overflow,
+    Symbol,
+}
+
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "{}", e),
+            ImageError::Magic => write!(f, "not an Intcode image"),
+            ImageError::Version(v) => write!(f, "unsupported image version {}", v),
+            ImageError::WordSize(s) => write!(f, "unsupported word size {}", s),
+            ImageError::Overflow => write!(f, "value too large for image word size"),
+            ImageError::Symbol => write!(f, "symbol name isn't valid UTF-8"),
+        }
+    }
+}
+
+
+impl std::error::Error for ImageError {}
+
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> ImageError {
+        ImageError::Io(err)
+    }
+}
+
+
+impl Image {
+
+    /*
+     * Image of a plain program, entered at 0 with no symbols
+     */
+    pub fn new(words: &[i64]) -> Image {
+        Image {
+            entry: 0,
+            words: words.to_vec(),
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    /*
+     * Machine loaded with the image's words, ready to start at its entry
+     */
+    pub fn boot(&self) -> Intcode {
+        let mut prog = self.words.clone();
+        prog.resize(prog.len() + PADDING, 0);
+
+        Intcode::resume(prog, self.entry, 0)
+    }
+
+    /*
+     * Smallest supported word size holding every word of the program
+     */
+    fn word_size(&self) -> u8 {
+        let small = self.words.iter().all(|w| *w >= i32::MIN as i64 && *w <= i32::MAX as i64);
+
+        if small { 4 } else { 8 }
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let flags = if self.symbols.is_empty() { 0 } else { FLAG_SYMBOLS };
+
+        w.write_all(&MAGIC)?;
+        w.write_all(&[VERSION, self.word_size(), flags])?;
+        write_varint(w, self.entry as u64)?;
+        write_varint(w, self.words.len() as u64)?;
+
+        for word in self.words.iter() {
+            write_varint(w, zigzag(*word))?;
+        }
+
+        if ! self.symbols.is_empty() {
+            write_varint(w, self.symbols.len() as u64)?;
+
+            for (name, addr) in self.symbols.iter() {
+                write_varint(w, name.len() as u64)?;
+                w.write_all(name.as_bytes())?;
+                write_varint(w, *addr as u64)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Image, ImageError> {
+        let mut header = [0; 7];
+        r.read_exact(&mut header)?;
+
+        if header[..4] != MAGIC {
+            return Err(ImageError::Magic);
+        }
+
+        let (version, size, flags) = (header[4], header[5], header[6]);
+
+        if version != VERSION {
+            return Err(ImageError::Version(version));
+        }
+
+        let (lo, hi) = match size {
+            4 => (i32::MIN as i64, i32::MAX as i64),
+            8 => (i64::MIN, i64::MAX),
+            _ => return Err(ImageError::WordSize(size))
+        };
+
+        let entry = read_varint(r)? as usize;
+        let count = read_varint(r)?;
+        let mut words = Vec::new();
+
+        // words are pushed as they're read rather than allocated from the
+        // count, so a corrupt count runs out of input instead of memory
+        for _ in 0..count {
+            let word = match read_varint(r) {
+                Err(ImageError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(short("words")),
+                word => unzigzag(word?)
+            };
+
+            if word < lo || word > hi {
+                return Err(ImageError::Overflow);
+            }
+
+            words.push(word);
+        }
+
+        let mut symbols = BTreeMap::new();
+
+        if flags & FLAG_SYMBOLS != 0 {
+            for _ in 0..read_varint(r)? {
+                let len = read_varint(r)?;
+                let mut name = Vec::new();
+                r.by_ref().take(len).read_to_end(&mut name)?;
+
+                if name.len() as u64 != len {
+                    return Err(short("symbol name"));
+                }
+
+                let name = String::from_utf8(name).map_err(|_| ImageError::Symbol)?;
+                symbols.insert(name, read_varint(r)? as usize);
+            }
+        }
+
+        Ok(Image { entry, words, symbols })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Image, ImageError> {
+        Image::read(&mut bytes)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        self.write(&mut File::create(path)?)
+    }
+
+    pub fn open(path: &str) -> Result<Image, ImageError> {
+        Image::read(&mut File::open(path)?)
+    }
+}
+
+
+/*
+ * Error for an image that ends partway through what it said was coming
+ */
+fn short(what: &str) -> ImageError {
+    ImageError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("image ends partway through its {}", what)))
+}
+
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+
+fn unzigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+
+fn write_varint<W: Write>(w: &mut W, mut n: u64) -> io::Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+
+        if n == 0 {
+            return w.write_all(&[byte]);
+        }
+
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+
+fn read_varint<R: Read>(r: &mut R) -> Result<u64, ImageError> {
+    let mut n = 0;
+    let mut shift = 0;
+
+    loop {
+        let mut byte = [0];
+        r.read_exact(&mut byte)?;
+
+        if shift > 63 || (shift == 63 && byte[0] & 0x7e != 0) {
+            return Err(ImageError::Overflow);
+        }
+
+        n |= ((byte[0] & 0x7f) as u64) << shift;
+        shift += 7;
+
+        if byte[0] & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcoder::IntResponse;
+    use crate::loader;
+
+    #[test]
+    fn test_zigzag() {
+        for n in [0, 1, -1, 63, -64, 64, i64::MAX, i64::MIN].iter() {
+            assert_eq!(unzigzag(zigzag(*n)), *n);
+        }
+
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, 300).unwrap();
+        assert_eq!(bytes, vec!(0xac, 0x02));
+        assert_eq!(read_varint(&mut &bytes[..]).unwrap(), 300);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for path in ["program.txt", "test.txt", "123.txt"].iter() {
+            let prog = loader::load(path).unwrap();
+            let image = Image::new(&prog);
+            let bytes = image.to_bytes();
+
+            assert_eq!(&bytes[..4], b"INTC");
+            assert_eq!(Image::from_bytes(&bytes).unwrap(), image);
+        }
+
+        let mut image = Image::new(&[104,1125899906842624,99]);
+        image.entry = 2;
+        image.symbols.insert("start".to_string(), 0);
+        image.symbols.insert("end".to_string(), 2);
+
+        let bytes = image.to_bytes();
+        assert_eq!(bytes[5], 8);
+        assert_eq!(Image::from_bytes(&bytes).unwrap(), image);
+    }
+
+    #[test]
+    fn test_bad_images() {
+        let bytes = Image::new(&[1,2,3]).to_bytes();
+
+        assert!(matches!(Image::from_bytes(b"1,2,3,4,5,6,7"), Err(ImageError::Magic)));
+        assert!(matches!(Image::from_bytes(&bytes[..bytes.len() - 1]), Err(ImageError::Io(_))));
+
+        let mut bad = bytes.clone();
+        bad[4] = 9;
+        assert!(matches!(Image::from_bytes(&bad), Err(ImageError::Version(9))));
+
+        // a word too big for the 4 byte size declared
+        let mut bad = Image::new(&[1 << 40]).to_bytes();
+        bad[5] = 4;
+        assert!(matches!(Image::from_bytes(&bad), Err(ImageError::Overflow)));
+    }
+
+    #[test]
+    fn test_short_images() {
+        let invalid = |r: Result<Image, ImageError>| match r {
+            Err(ImageError::Io(e)) => e.kind() == io::ErrorKind::InvalidData,
+            _ => false
+        };
+
+        // word count of 2^62 with only one word present
+        let mut bad = b"INTC\x01\x04\x00\x00\x80\x80\x80\x80\x80\x80\x80\x80\x40".to_vec();
+        bad.push(2);
+        assert!(invalid(Image::from_bytes(&bad)));
+
+        // one symbol with a name 2^62 bytes long
+        let bad = b"INTC\x01\x04\x01\x00\x01\x02\x01\x80\x80\x80\x80\x80\x80\x80\x80\x40ab";
+        assert!(invalid(Image::from_bytes(bad)));
+
+        let mut image = Image::new(&[1,2,3]);
+        image.symbols.insert("start".to_string(), 0);
+        let bytes = image.to_bytes();
+        assert!(invalid(Image::from_bytes(&bytes[..bytes.len() - 3])));
+    }
+
+    #[test]
+    fn test_boot() {
+        let mut image = Image::new(&[104,1,104,2,99]);
+        image.entry = 2;
+
+        let mut computer = image.boot();
+        assert_eq!(computer.run(), IntResponse::Output(2));
+        assert_eq!(computer.run(), IntResponse::Halt);
+    }
+}
@@ -1,12 +1,134 @@
-pub struct Intcode {
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+// Zeroed memory added past the end of every loaded program
+pub const PADDING: usize = 5000;
+
+
+pub struct Intcode<O: Observer = NoObserver> {
     prog: Vec<i64>,
     ip: usize,
     rel: i64,
-    input: Option<i64>
+    input: Option<i64>,
+    policy: InputPolicy,
+    default_reads: u64,
+    devices: Vec<Mapping>,
+    observer: O
+}
+
+
+/*
+ * Hooks called as a program runs, for tracers, profilers, coverage and
+ * debuggers. Every hook does nothing by default so observers only
+ * implement what they care about. Reads and writes are only the data
+ * accesses made by parameters, not fetching instructions.
+ */
+pub trait Observer {
+    fn on_instruction(&mut self, _ip: usize, _instruction: i64) {}
+    fn on_read(&mut self, _addr: usize, _val: i64) {}
+    fn on_write(&mut self, _addr: usize, _val: i64) {}
+    fn on_input(&mut self, _val: i64) {}
+    fn on_output(&mut self, _val: i64) {}
+    fn on_halt(&mut self, _ip: usize) {}
+}
+
+
+/*
+ * Observer of a plain Intcode, its empty hooks compile away to nothing
+ */
+pub struct NoObserver;
+
+
+impl Observer for NoObserver {}
+
+
+/*
+ * Peripheral that can be mapped onto a range of machine memory. Reads and
+ * writes by the program to that range go to the device instead, with the
+ * address given as an offset from the start of the range. Devices are
+ * Send so a machine holding them can still be moved to another thread.
+ */
+pub trait Device: Send {
+    fn read(&mut self, offset: usize) -> i64;
+    fn write(&mut self, offset: usize, val: i64);
+}
+
+
+/*
+ * Lets a caller keep a handle on a device after mapping it, to look at
+ * its state while or after the program runs
+ */
+impl<D: Device> Device for Arc<Mutex<D>> {
+    fn read(&mut self, offset: usize) -> i64 {
+        self.lock().unwrap().read(offset)
+    }
+
+    fn write(&mut self, offset: usize, val: i64) {
+        self.lock().unwrap().write(offset, val)
+    }
+}
+
+
+/*
+ * Single memory write applied to a program before it runs, e.g. setting
+ * the noun and verb at addresses 1 and 2. Parses from "addr=val".
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Patch {
+    pub addr: usize,
+    pub val: i64,
+}
+
+
+impl FromStr for Patch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Patch, String> {
+        let mut parts = s.splitn(2, '=');
+        let addr = parts.next().unwrap_or("").trim().parse();
+        let val = parts.next().unwrap_or("").trim().parse();
+
+        match (addr, val) {
+            (Ok(addr), Ok(val)) => Ok(Patch { addr, val }),
+            _ => Err(format!("bad patch `{}`, expected addr=val", s))
+        }
+    }
+}
+
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.addr, self.val)
+    }
+}
+
+
+struct Mapping {
+    start: usize,
+    end: usize,
+    device: Box<dyn Device + Send>
 }
 
 
-#[derive(Debug)]
+/*
+ * Anything that can execute an Intcode program the way Intcode does,
+ * so other backends can be swapped in and checked against it
+ */
+pub trait Engine {
+    fn input(&mut self, num: i64) -> bool;
+    fn run(&mut self) -> IntResponse;
+
+    /*
+     * Like run but gives up after this many instructions, returning None
+     * if the program was still going
+     */
+    fn run_for(&mut self, steps: u64) -> Option<IntResponse>;
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IntResponse {
     Halt,
     Input,
@@ -14,7 +136,29 @@ pub enum IntResponse {
 }
 
 
-#[derive(Debug)]
+/*
+ * What opcode 3 does when no input has been provided. Networked programs
+ * expect to read -1 when nothing has arrived rather than wait for it.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputPolicy {
+    Block,
+    Default(i64)
+}
+
+
+/*
+ * What a run_with handler wants done after seeing a response
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reply {
+    Continue,
+    Input(i64),
+    Stop
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IntMode {
     Pos,
     Imm,
@@ -22,23 +166,114 @@ pub enum IntMode {
 }
 
 
+impl IntMode {
+
+    /*
+     * Mode for a single parameter digit, None if digit isn't a valid mode
+     */
+    pub fn new(bit: i64) -> Option<IntMode> {
+        match bit {
+            0 => Some(IntMode::Pos),
+            1 => Some(IntMode::Imm),
+            2 => Some(IntMode::Rel),
+            _ => None
+        }
+    }
+}
+
+
 impl Intcode {
 
-    pub fn new(to_copy: &Vec<i64>) -> Intcode {
-        let mut prog = to_copy.clone();
+    pub fn new(to_copy: &[i64]) -> Intcode {
+        let mut prog = to_copy.to_vec();
 
         // TODO
         // Shouldn't just niavely add space to end, ideally
         // use checks in self.store/self.fetch to see if we
         // exceed end of array then pad zeroes to fit it
-        prog.resize(prog.len() + 5000, 0);
+        prog.resize(prog.len() + PADDING, 0);
 
         Intcode {
-            prog: prog,
+            prog,
             ip: 0,
             rel: 0,
-            input: None
+            input: None,
+            policy: InputPolicy::Block,
+            default_reads: 0,
+            devices: Vec::new(),
+            observer: NoObserver
+        }
+    }
+
+    /*
+     * Load a program with the given patches applied to it
+     */
+    pub fn with_patches(to_copy: &[i64], patches: &[Patch]) -> Intcode {
+        let mut icoder = Intcode::new(to_copy);
+        icoder.patch(patches);
+        icoder
+    }
+
+    /*
+     * Resume a machine from a snapshot of its memory and registers, for
+     * callers that ran the program some other way up to this point.
+     * Memory is used as is, no padding is added.
+     */
+    pub fn resume(prog: Vec<i64>, ip: usize, rel: i64) -> Intcode {
+        Intcode {
+            prog,
+            ip,
+            rel,
+            input: None,
+            policy: InputPolicy::Block,
+            default_reads: 0,
+            devices: Vec::new(),
+            observer: NoObserver
+        }
+    }
+}
+
+
+impl<O: Observer> Intcode<O> {
+
+    /*
+     * Same machine with an observer attached, replacing any current one
+     */
+    pub fn observe<P: Observer>(self, observer: P) -> Intcode<P> {
+        Intcode {
+            prog: self.prog,
+            ip: self.ip,
+            rel: self.rel,
+            input: self.input,
+            policy: self.policy,
+            default_reads: self.default_reads,
+            devices: self.devices,
+            observer
+        }
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /*
+     * Map a device onto len words of memory starting at start. Returns false
+     * and leaves the device unmapped if the range overlaps another device.
+     */
+    pub fn map<D: Device + 'static>(&mut self, start: usize, len: usize, device: D) -> bool {
+        let end = start + len;
+
+        if self.devices.iter().any(|m| start < m.end && m.start < end) {
+            return false;
         }
+
+        self.devices.push(Mapping { start, end, device: Box::new(device) });
+
+        true
     }
 
     /*
@@ -46,16 +281,45 @@ impl Intcode {
      * and prior input didn't exit
      */
     pub fn input(&mut self, num: i64) -> bool {
-        match self.input {
-            Some(i) => return false,
-            None => {
-                self.input = Some(num);
-            }
-        };
+        if self.input.is_some() {
+            return false;
+        }
+
+        self.input = Some(num);
 
         true
     }
 
+    /*
+     * Choose between stopping with IntResponse::Input when the program
+     * wants input that hasn't been provided, the default, or reading a
+     * fixed value and carrying on
+     */
+    pub fn set_input_policy(&mut self, policy: InputPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn input_policy(&self) -> InputPolicy {
+        self.policy
+    }
+
+    /*
+     * Number of times the program read the default value because no input
+     * was there. A count that keeps rising while nothing is output means
+     * the program is idle, waiting on input that isn't coming.
+     */
+    pub fn default_reads(&self) -> u64 {
+        self.default_reads
+    }
+
+    /*
+     * Address of the next instruction, or of the input instruction the
+     * program is waiting on after IntResponse::Input
+     */
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
     /*
      * Run program with provdided input if input doesn't already exist.
      * Exists to support old code/tests where run utilized argument.
@@ -74,73 +338,134 @@ impl Intcode {
      * IntResponse::Halt      -> progam has completed
      */
     pub fn run(&mut self) -> IntResponse {
-        let mut halt = false;
-        let mut result = IntResponse::Halt;
-
-        while ! halt {
-            let instruction = self.fetch(IntMode::Imm);
-            let (code, first_mode, second_mode, third_mode) = self.decode(instruction);
-
-
-            match code {
-
-                // ARITHMETIC
-                1|2|7|8 => {
-                    let first = self.fetch(first_mode);
-                    let second = self.fetch(second_mode);
-                    let value = self.arithmetic(code, first, second);
-
-                    self.store(third_mode, value);
-                },
-
-                // I/O
-                3 => {
-                    match first_mode {
-                        IntMode::Imm => panic!("Immediate mode for input doesn't make sense!"),
-                        _ => {}
-                    };
-
-                    match self.input {
-                        Some(i) => {
-                            self.store(first_mode, i);
-                            self.input = None;
-                        },
-                        None => {
-                            self.ip -= 1;
-                            result = IntResponse::Input;
-                            halt = true;
-                        }
-                    }
-                },
-                4 => {
-                    halt = true;
-                    result = IntResponse::Output(self.fetch(first_mode));
-                },
-
-                // JUMP
-                5|6 => {
-                    let first = self.fetch(first_mode);
-                    let second = self.fetch(second_mode);
-
-                    self.ip = self.jmp(code, first, second);
-                },
-
-                // Relative set
-                9 => {
-                    self.rel += self.fetch(first_mode);
-                },
-
-                99 => {
-                    halt = true;
-                },
-
-                _ => panic!("bad opcode! {} at {}", code, self.ip),
-            };
+        loop {
+            if let Some(response) = self.execute() {
+                return response;
+            }
+        }
+    }
+
+    /*
+     * Run at most steps instructions, stopping early the same way run
+     * does. None if the program was still going, which lets a caller
+     * check on a program that reads a default input instead of blocking.
+     */
+    pub fn run_for(&mut self, steps: u64) -> Option<IntResponse> {
+        for _ in 0..steps {
+            if let Some(response) = self.execute() {
+                return Some(response);
+            }
+        }
+
+        None
+    }
+
+    /*
+     * Execute one instruction, returning what run should if it stops there
+     */
+    fn execute(&mut self) -> Option<IntResponse> {
+        let mut result = None;
+
+        let instruction = self.fetch(IntMode::Imm);
+        let (code, first_mode, second_mode, third_mode) = self.decode(instruction);
+
+        // a blocked input isn't executed, it will be again once resumed
+        if code != 3 || self.input.is_some() || self.policy != InputPolicy::Block {
+            self.observer.on_instruction(self.ip - 1, instruction);
         }
 
+        match code {
+
+            // ARITHMETIC
+            1|2|7|8 => {
+                let first = self.fetch(first_mode);
+                let second = self.fetch(second_mode);
+                let value = self.arithmetic(code, first, second);
+
+                self.store(third_mode, value);
+            },
+
+            // I/O
+            3 => {
+                if let IntMode::Imm = first_mode {
+                    panic!("Immediate mode for input doesn't make sense!");
+                }
+
+                let value = match (self.input.take(), self.policy) {
+                    (Some(i), _) => Some(i),
+                    (None, InputPolicy::Default(i)) => {
+                        self.default_reads += 1;
+                        Some(i)
+                    },
+                    (None, InputPolicy::Block) => None
+                };
+
+                match value {
+                    Some(i) => {
+                        self.observer.on_input(i);
+                        self.store(first_mode, i);
+                    },
+                    None => {
+                        self.ip -= 1;
+                        result = Some(IntResponse::Input);
+                    }
+                }
+            },
+            4 => {
+                let value = self.fetch(first_mode);
+
+                self.observer.on_output(value);
+                result = Some(IntResponse::Output(value));
+            },
+
+            // JUMP
+            5|6 => {
+                let first = self.fetch(first_mode);
+                let second = self.fetch(second_mode);
+
+                self.ip = self.jmp(code, first, second);
+            },
+
+            // Relative set
+            9 => {
+                self.rel += self.fetch(first_mode);
+            },
+
+            99 => {
+                self.observer.on_halt(self.ip - 1);
+                result = Some(IntResponse::Halt);
+            },
+
+            _ => panic!("bad opcode! {} at {}", code, self.ip),
+        };
+
         result
     }
 
+    /*
+     * Run program, handing every input request and output to handler
+     * instead of returning. The handler answers an input request with
+     * Reply::Input and an output with Reply::Continue to keep going, any
+     * other reply stops the run. Returns the response the run stopped on,
+     * which is Halt if the program finished.
+     */
+    pub fn run_with<F: FnMut(&IntResponse) -> Reply>(&mut self, mut handler: F) -> IntResponse {
+        loop {
+            let response = self.run();
+
+            let reply = match response {
+                IntResponse::Halt => return response,
+                _ => handler(&response)
+            };
+
+            match (&response, reply) {
+                (IntResponse::Input, Reply::Input(i)) => { self.input(i); },
+                (IntResponse::Output(_), Reply::Continue) => {},
+                _ => return response
+            };
+        }
+    }
+
     /*
      * Decodes and incode instruction, extracting the code and any parameter modes.
      * Default mode is positional mode.
@@ -150,9 +475,9 @@ impl Intcode {
         let mut mode = instruction / 100;
 
         let first = mode % 10;
-        mode = mode / 10;
+        mode /= 10;
         let second = mode % 10;
-        mode = mode / 10;
+        mode /= 10;
         let third = mode % 10;
 
         (code, self.mode(first), self.mode(second), self.mode(third))
@@ -173,18 +498,21 @@ impl Intcode {
             IntMode::Rel => (self.prog[store] + self.rel) as usize
         };
 
-        self.prog[store] = val;
+        self.observer.on_write(store, val);
+
+        match self.devices.iter_mut().find(|m| m.start <= store && store < m.end) {
+            Some(m) => m.device.write(store - m.start, val),
+            None => self.prog[store] = val
+        };
     }
 
     /*
      * Return mode of given input
      */
     fn mode(&self, bit: i64) -> IntMode {
-        match bit {
-            0 => IntMode::Pos,
-            1 => IntMode::Imm,
-            2 => IntMode::Rel,
-            _ => panic!("bad bit mode!")
+        match IntMode::new(bit) {
+            Some(mode) => mode,
+            None => panic!("bad bit mode!")
         }
     }
 
@@ -201,12 +529,26 @@ impl Intcode {
         self.ip += 1;
         
         match mode {
-            IntMode::Pos => self.prog[val as usize],
+            IntMode::Pos => self.load(val as usize),
             IntMode::Imm => val,
-            IntMode::Rel => self.prog[(val + self.rel) as usize]
+            IntMode::Rel => self.load((val + self.rel) as usize)
         }
     }
 
+    /*
+     * Value at an address, read from a device if one is mapped there
+     */
+    fn load(&mut self, addr: usize) -> i64 {
+        let val = match self.devices.iter_mut().find(|m| m.start <= addr && addr < m.end) {
+            Some(m) => m.device.read(addr - m.start),
+            None => self.prog[addr]
+        };
+
+        self.observer.on_read(addr, val);
+
+        val
+    }
+
     /*
      * All jump code intructions. Returns new IP location based on
      * whether given test condition passes
@@ -240,12 +582,63 @@ impl Intcode {
     }
 
 
-    fn dump(&self) {
+    /*
+     * Value at addr. Memory is plain memory here, mapped devices aren't
+     * consulted, and anything past the end reads as 0.
+     */
+    pub fn read(&self, addr: usize) -> i64 {
+        self.prog.get(addr).cloned().unwrap_or(0)
+    }
+
+    /*
+     * Set the value at addr, growing memory if addr is past the end
+     */
+    pub fn write(&mut self, addr: usize, val: i64) {
+        if addr >= self.prog.len() {
+            self.prog.resize(addr + 1, 0);
+        }
+
+        self.prog[addr] = val;
+    }
+
+    pub fn patch(&mut self, patches: &[Patch]) {
+        for p in patches {
+            self.write(p.addr, p.val);
+        }
+    }
+
+    /*
+     * View of a range of memory, panics if it runs past the end
+     */
+    pub fn slice(&self, range: Range<usize>) -> &[i64] {
+        &self.prog[range]
+    }
+
+    pub fn slice_mut(&mut self, range: Range<usize>) -> &mut [i64] {
+        &mut self.prog[range]
+    }
+
+    pub fn dump(&self) {
         println!("{:?}", self.prog);
     }
 }
 
 
+impl<O: Observer> Engine for Intcode<O> {
+    fn input(&mut self, num: i64) -> bool {
+        Intcode::input(self, num)
+    }
+
+    fn run(&mut self) -> IntResponse {
+        Intcode::run(self)
+    }
+
+    fn run_for(&mut self, steps: u64) -> Option<IntResponse> {
+        Intcode::run_for(self, steps)
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -569,4 +962,91 @@ mod tests {
         assert_eq!(answer, 9);
     }
 
+    #[test]
+    fn test_run_with() {
+        // output double each input until given 0
+        let prog = vec!(3,20,1006,20,14,1002,20,2,21,4,21,1105,1,0,99);
+        let mut inputs = vec!(3, -4, 0).into_iter();
+        let mut outputs = Vec::new();
+
+        let mut icoder = Intcode::new(&prog);
+        let response = icoder.run_with(|r| match r {
+            IntResponse::Input => Reply::Input(inputs.next().unwrap()),
+            IntResponse::Output(i) => {
+                outputs.push(*i);
+                Reply::Continue
+            },
+            IntResponse::Halt => Reply::Stop
+        });
+
+        assert!(matches!(response, IntResponse::Halt));
+        assert_eq!(outputs, vec!(6, -8));
+
+        let mut icoder = Intcode::new(&prog);
+        let response = icoder.run_with(|r| match r {
+            IntResponse::Input => Reply::Input(5),
+            _ => Reply::Stop
+        });
+        assert!(matches!(response, IntResponse::Output(10)));
+    }
+
+    #[test]
+    fn test_memory() {
+        let prog = vec!(1,0,0,0,99,30,40,50);
+        let patches: Vec<Patch> = vec!("1=5", "2=6").into_iter().map(|p| p.parse().unwrap()).collect();
+        let mut icoder = Intcode::with_patches(&prog, &patches);
+
+        assert_eq!(icoder.slice(0..4), &[1,5,6,0]);
+        assert!(matches!(icoder.run(), IntResponse::Halt));
+        assert_eq!(icoder.read(0), 70);
+
+        icoder.write(100000, 7);
+        assert_eq!(icoder.read(100000), 7);
+        assert_eq!(icoder.read(200000), 0);
+
+        icoder.slice_mut(5..7).copy_from_slice(&[1,2]);
+        assert_eq!(icoder.slice(5..8), &[1,2,50]);
+
+        assert!("1".parse::<Patch>().is_err());
+        assert_eq!(Patch { addr: 0, val: 2 }.to_string(), "0=2");
+    }
+
+    #[test]
+    fn test_input_policy() {
+        // output the sum of two inputs
+        let add = vec!(3,11,3,12,1,11,12,13,4,13,99,0,0,0);
+
+        let mut icoder = Intcode::new(&add);
+        icoder.input(5);
+        assert!(matches!(icoder.run(), IntResponse::Input));
+        assert_eq!(icoder.default_reads(), 0);
+
+        let mut icoder = Intcode::new(&add);
+        icoder.set_input_policy(InputPolicy::Default(-1));
+        icoder.input(5);
+        assert!(matches!(icoder.run(), IntResponse::Output(4)));
+        assert_eq!(icoder.default_reads(), 1);
+
+        let mut icoder = Intcode::new(&add);
+        icoder.set_input_policy(InputPolicy::Default(-1));
+        assert!(matches!(icoder.run(), IntResponse::Output(-2)));
+        assert_eq!(icoder.default_reads(), 2);
+        assert_eq!(icoder.input_policy(), InputPolicy::Default(-1));
+    }
+
+    #[test]
+    fn test_run_for() {
+        // poll until something other than -1 arrives, then output it
+        let poll = vec!(3,12,1008,12,-1,13,1005,13,0,4,12,99,0,0);
+
+        let mut icoder = Intcode::new(&poll);
+        icoder.set_input_policy(InputPolicy::Default(-1));
+        assert_eq!(icoder.run_for(30), None);
+        assert_eq!(icoder.default_reads(), 10);
+
+        icoder.input(6);
+        assert_eq!(icoder.run_for(30), Some(IntResponse::Output(6)));
+        assert_eq!(icoder.run(), IntResponse::Halt);
+    }
+
 }
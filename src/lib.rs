@@ -0,0 +1,20 @@
+pub mod intcoder;
+pub mod explorer;
+pub mod disasm;
+pub mod cfg;
+pub mod decompiler;
+pub mod transpiler;
+pub mod rng;
+pub mod fuzz;
+pub mod loader;
+pub mod image;
+pub mod devices;
+pub mod search;
+pub mod observers;
+pub mod pipeline;
+pub mod optimizer;
+pub mod assembler;
+pub mod compiler;
+pub mod multicoder;
+pub mod capture;
+pub mod remote;
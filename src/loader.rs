@@ -0,0 +1,204 @@
+use crate::image::{self, Image, ImageError};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io { source: String, err: io::Error },
+    Parse { source: String, line: usize, col: usize, msg: String },
+    Image { source: String, err: ImageError },
+}
+
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { source, err } => write!(f, "{}: {}", source, err),
+            LoadError::Parse { source, line, col, msg } => write!(f, "{}:{}:{}: {}", source, line, col, msg),
+            LoadError::Image { source, err } => write!(f, "{}: {}", source, err),
+        }
+    }
+}
+
+
+impl std::error::Error for LoadError {}
+
+
+impl From<LoadError> for io::Error {
+    fn from(err: LoadError) -> io::Error {
+        match err {
+            LoadError::Io { err, .. } => err,
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
+
+
+/*
+ * Parses program text. Values are separated by commas, whitespace or both,
+ * so programs can be wrapped over several lines, and `#` starts a comment
+ * running to the end of the line. A comma with no value before it is an
+ * error, a trailing comma is not. Errors name the source along with the
+ * line and column (both from 1) of the offending text.
+ */
+pub fn parse(text: &str, source: &str) -> Result<Vec<i64>, LoadError> {
+    let mut prog = Vec::new();
+    let mut token = String::new();
+    let mut start = (1, 1);
+    let mut separated = true;
+    let mut comment = false;
+    let (mut line, mut col) = (1, 0);
+
+    let error = |line, col, msg: String| LoadError::Parse { source: source.to_string(), line, col, msg };
+
+    for c in text.chars().chain(Some('\n')) {
+        col += 1;
+
+        if ! comment && ! token.is_empty() && (c == ',' || c == '#' || c.is_whitespace()) {
+            match token.parse() {
+                Ok(v) => prog.push(v),
+                Err(_) => return Err(error(start.0, start.1, format!("invalid value `{}`", token)))
+            };
+            token.clear();
+            separated = false;
+        }
+
+        match c {
+            '\n' => {
+                line += 1;
+                col = 0;
+                comment = false;
+            },
+            _ if comment || c.is_whitespace() => {},
+            '#' => comment = true,
+            ',' => {
+                if separated {
+                    return Err(error(line, col, "missing value before `,`".to_string()));
+                }
+                separated = true;
+            },
+            _ => {
+                if token.is_empty() {
+                    start = (line, col);
+                }
+                token.push(c);
+            }
+        };
+    }
+
+    Ok(prog)
+}
+
+
+/*
+ * Loads a program from a file, or from stdin if path is `-`. Binary images
+ * are recognized by their magic number, anything else is parsed as text
+ * and given an image entered at 0. Image::boot starts a machine at the
+ * image's entry.
+ */
+pub fn load_image(path: &str) -> Result<Image, LoadError> {
+    let mut contents = Vec::new();
+
+    let result = if path == "-" {
+        io::stdin().read_to_end(&mut contents)
+    } else {
+        File::open(path).and_then(|mut f| f.read_to_end(&mut contents))
+    };
+
+    let source = if path == "-" { "<stdin>" } else { path };
+
+    if let Err(err) = result {
+        return Err(LoadError::Io { source: source.to_string(), err });
+    }
+
+    if contents.starts_with(&image::MAGIC) {
+        return match Image::from_bytes(&contents) {
+            Ok(image) => Ok(image),
+            Err(err) => Err(LoadError::Image { source: source.to_string(), err })
+        };
+    }
+
+    match String::from_utf8(contents) {
+        Ok(text) => parse(&text, source).map(|prog| Image::new(&prog)),
+        Err(e) => Err(LoadError::Io {
+            source: source.to_string(),
+            err: io::Error::new(io::ErrorKind::InvalidData, e)
+        })
+    }
+}
+
+
+/*
+ * Just the words of a program, see load_image
+ */
+pub fn load(path: &str) -> Result<Vec<i64>, LoadError> {
+    load_image(path).map(|image| image.words)
+}
+
+
+/*
+ * Loads several files (or stdin) and concatenates them into one program
+ */
+pub fn load_all<S: AsRef<str>>(paths: &[S]) -> Result<Vec<i64>, LoadError> {
+    let mut prog = Vec::new();
+
+    for path in paths {
+        prog.extend(load(path.as_ref())?);
+    }
+
+    Ok(prog)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let prog = load("123.txt").unwrap();
+        assert_eq!(prog, vec!(1,2,3));
+
+        let prog = load_all(&["123.txt", "123.txt"]).unwrap();
+        assert_eq!(prog, vec!(1,2,3,1,2,3));
+
+        let path = std::env::temp_dir().join(format!("loader-{}.img", std::process::id()));
+        let path = path.to_str().unwrap();
+        Image::new(&prog).save(path).unwrap();
+        assert_eq!(load(path).unwrap(), prog);
+
+        let mut image = Image::new(&prog);
+        image.entry = 3;
+        image.save(path).unwrap();
+        assert_eq!(load_image(path).unwrap(), image);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(load_image("123.txt").unwrap(), Image::new(&[1,2,3]));
+
+        match load("missing.txt") {
+            Err(LoadError::Io { source, .. }) => assert_eq!(source, "missing.txt"),
+            r => panic!("expected io error, got {:?}", r)
+        };
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "# add two inputs\n3,9, 3,10,\n  1,9,10,9 # sum\n4,9\n99,\n";
+        assert_eq!(parse(text, "t").unwrap(), vec!(3,9,3,10,1,9,10,9,4,9,99));
+        assert_eq!(parse("1 2\t-3", "t").unwrap(), vec!(1,2,-3));
+        assert_eq!(parse("", "t").unwrap(), vec!());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse("1,2,\n3,x4,5", "prog.txt").unwrap_err();
+        assert_eq!(err.to_string(), "prog.txt:2:3: invalid value `x4`");
+
+        let err = parse("1,,2", "prog.txt").unwrap_err();
+        assert_eq!(err.to_string(), "prog.txt:1:3: missing value before `,`");
+
+        let err = parse("  ,1", "prog.txt").unwrap_err();
+        assert_eq!(err.to_string(), "prog.txt:1:3: missing value before `,`");
+    }
+}
@@ -1,22 +1,93 @@
-use std::fs::File;
-use std::io::{self, Read, prelude::*};
+use std::env;
+use std::io;
+use std::process;
 use std::time::{Instant};
-use std::time;
 
-mod intcoder;
-mod explorer;
+use three::{explorer, decompiler, transpiler, loader, image, assembler, compiler, multicoder, remote};
 
 fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() > 2 && args[1] == "decompile" {
+        print!("{}", decompiler::decompile(&load(&args[2..])));
+        return Ok(());
+    }
+
+    if args.len() > 2 && args[1] == "transpile" {
+        print!("{}", transpiler::transpile(&load(&args[2..]), "three::intcoder"));
+        return Ok(());
+    }
+
+    if args.len() > 3 && args[1] == "pack" {
+        image::Image::new(&load(&args[3..])).save(&args[2])?;
+        return Ok(());
+    }
+
+    if args.len() > 3 && args[1] == "asm" {
+        match assembler::assemble_file(&args[3]) {
+            Ok(image) => image.save(&args[2])?,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        };
+        return Ok(());
+    }
+
+    if args.len() > 3 && args[1] == "compile" {
+        match compiler::compile_file(&args[3]) {
+            Ok(prog) => image::Image::new(&prog).save(&args[2])?,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        };
+        return Ok(());
+    }
+
+    // amplifiers set to the comma separated phases, fed back from the last
+    // to the first, then how each of them spent the run
+    if args.len() > 3 && args[1] == "amplify" {
+        let phases: Vec<i64> = match args[2].split(',').map(|p| p.trim().parse()).collect() {
+            Ok(phases) => phases,
+            Err(e) => {
+                eprintln!("bad phases {}: {}", args[2], e);
+                process::exit(1);
+            }
+        };
+
+        let mut mcoder = multicoder::MultiCoder::new(&load(&args[3..]), phases.len());
+
+        for (i, p) in phases.iter().enumerate() {
+            mcoder.send(i, *p);
+        }
+
+        match mcoder.feedback(0) {
+            Some(signal) => println!("signal: {}", signal),
+            None => println!("no signal")
+        };
+
+        print!("{}", mcoder.summary());
+        return Ok(());
+    }
+
+    // one machine of a network started by MultiCoder::processes, which
+    // passes the socket to connect back on and the node's id in the
+    // environment
+    if args.len() > 1 && args[1] == "node" {
+        return remote::node();
+    }
+
     let now = Instant::now();
-    let prog = read("program.txt")?; 
+    let image = loader::load_image("program.txt")?; 
 
-    let mut computer = intcoder::Intcode::new(&prog);
+    let mut computer = image.boot();
     let mut exp = explorer::Explorer::new(&mut computer);
     println!("path from start to oxy: {}", exp.run(false));
     
     exp.print();
 
-    let mut computer = intcoder::Intcode::new(&prog);
+    let mut computer = image.boot();
     let mut exp = explorer::Explorer::new(&mut computer);
     println!("longest path from oxy: {}", exp.run(true));
 
@@ -31,19 +102,18 @@ fn main() -> io::Result<()> {
 }
 
 
-fn read(filename: &str) -> Result<Vec<i64>,std::io::Error> {
-    let mut file = File::open(filename)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-
-    contents = contents.trim().to_string();
-    
-    Ok(contents.split(',').map( |x| {
-        match x.parse() {
-            Ok(x) => x,
-            _ => -1
+/*
+ * Loads the programs named on the command line, exiting with the loader's
+ * message if any of them can't be read
+ */
+fn load(paths: &[String]) -> Vec<i64> {
+    match loader::load_all(paths) {
+        Ok(prog) => prog,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
         }
-    }).collect())
+    }
 }
 
 
@@ -53,7 +123,7 @@ mod tests {
 
     #[test]
     fn test_read() -> io::Result<()> {
-        let prog = read("123.txt")?;
+        let prog = loader::load("123.txt")?;
         assert_eq!(prog, vec!(1,2,3));
 
         Ok(())
@@ -1,100 +1,1002 @@
-use crate::intcoder::{Intcode, IntResponse};
+use crate::capture::Capture;
+use crate::intcoder::{Intcode, IntResponse, InputPolicy};
+use crate::observers::Counter;
+use crate::remote;
+use permutohedron::Heap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::process::Child;
 use std::thread;
-use std::sync::mpsc::{channel, Sender, Receiver};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
+
+/*
+ * Address packets sent to are stored by the NAT rather than delivered
+ */
+pub const NAT: usize = 255;
+
+
+/*
+ * What a machine reports back as it runs. Blocked carries how many
+ * inputs the machine had consumed when it ran out, so a wait that was
+ * reported before more input arrived can be told apart from a real one,
+ * and where it's waiting.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Event {
+    Output(i64),
+    Blocked(u64, usize),
+    Halt
+}
+
+
+/*
+ * How the machines take turns
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    // each machine on its own thread, running as the OS sees fit
+    Threads,
+    // one thread running each machine in turn until it blocks on input,
+    // so every run of a network goes the same way
+    RoundRobin,
+}
+
+
+enum Backend {
+    Threads {
+        inputs: Vec<Sender<Vec<i64>>>,
+        events: Receiver<(usize, Event)>,
+    },
+    RoundRobin {
+        machines: Vec<Machine>,
+        current: usize,
+    },
+    Processes {
+        inputs: Vec<Sender<Vec<i64>>>,
+        events: Receiver<(usize, Event)>,
+        children: Vec<Child>,
+        errors: Arc<Mutex<Vec<Option<String>>>>,
+    },
+}
+
+
+/*
+ * A network of machines. Inputs sent to a machine queue up until it asks
+ * for them and what the machines do comes back as events, whichever way
+ * they're scheduled. Machines can be wired together so one's outputs
+ * become others' inputs in any shape of network, chains, fan-out, fan-in
+ * and cycles included.
+ */
 pub struct MultiCoder {
-    senders : Vec<Sender<IntResponse>>,
-    receivers : Vec<Receiver<IntResponse>>
+    backend: Backend,
+    pending: Vec<VecDeque<Event>>,
+    routes: Vec<Vec<usize>>,
+    sent: Vec<u64>,
+    halted: Vec<bool>,
+    waiting: Vec<Option<usize>>,
+    boot: Vec<Vec<i64>>,
+    capture: Option<Capture>,
+    stats: Vec<Stats>,
 }
 
-impl MultiCoder {
 
-    pub fn new(prog: &Vec<i64>, size: usize) -> MultiCoder {
-        let mut senders = Vec::new();
-        let mut receivers = Vec::new();
-
-        for _i in 0..size {
-            let mut computer = Intcode::new(&prog);
-            let (from_tx, from_rx) = channel();
-            let (to_tx, to_rx) = channel();
-
-            thread::spawn(move || {
-                for received in to_rx {
-                    match received {
-                        IntResponse::Output(i) => {
-                            let response = computer.run(i);
-                            match &response {
-                                IntResponse::Halt => break,
-                                _ => {}
-                            }
-                            from_tx.send(response).unwrap();
-                        },
-                        _ => {}
+/*
+ * A packet between machines on a network
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Packet {
+    pub dest: usize,
+    pub x: i64,
+    pub y: i64,
+}
+
+
+/*
+ * What the NAT saw before the network settled: the y of the first packet
+ * sent to it and the first y it woke machine 0 with twice in a row
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NatReport {
+    pub first: i64,
+    pub repeated: i64,
+}
+
+
+/*
+ * How amplifiers are wired for a phase search: each feeding the next, or
+ * with the last also feeding back into the first
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Topology {
+    Chain,
+    Feedback,
+}
+
+
+/*
+ * The phase settings that gave the strongest signal
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    pub phases: Vec<i64>,
+    pub signal: i64,
+}
+
+
+/*
+ * Where a machine was when its network deadlocked: the address of the
+ * input it's waiting on, or None if it halted, and what it output that
+ * hasn't been taken yet
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeState {
+    pub ip: Option<usize>,
+    pub pending: Vec<i64>,
+}
+
+
+/*
+ * Every machine in a network either halted or waiting on input that no
+ * other machine is going to send
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deadlock {
+    pub nodes: Vec<NodeState>,
+}
+
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "deadlock, every machine is halted or waiting on input")?;
+
+        for (id, node) in self.nodes.iter().enumerate() {
+            match node.ip {
+                Some(ip) => write!(f, "\n  {}: waiting at {}", id, ip)?,
+                None => write!(f, "\n  {}: halted", id)?
+            };
+
+            if ! node.pending.is_empty() {
+                write!(f, ", pending outputs {:?}", node.pending)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/*
+ * What a machine has been up to. inputs counts every value the program
+ * read, -1s from polling an empty queue included. Time blocked runs from
+ * reporting itself blocked or idle to being given more input, or to
+ * outputting again for a machine that was polling.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Metrics {
+    pub instructions: u64,
+    pub inputs: u64,
+    pub outputs: u64,
+    pub blocked: Duration,
+    pub running: Duration,
+}
+
+
+/*
+ * A machine's metrics as of its last event, and when it blocked if it's
+ * still waiting, shared with whoever is running it
+ */
+pub(crate) type Stats = Arc<Mutex<(Metrics, Option<Instant>)>>;
+
+
+// Instructions a machine reading a default input runs between checks for
+// new input
+const SLICE: u64 = 1000;
+
+// Default reads in a row, with nothing received or output in between,
+// after which a machine counts as idle however long it computed between
+// them. More than one so a node can poll a few times before it sends.
+const IDLE_POLLS: u64 = 100;
+
+// How long an idle thread waits for input before polling again
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+
+/*
+ * A machine and the inputs waiting for it. With a default input policy a
+ * machine whose queue is empty reads the default instead of waiting, for
+ * as long as it keeps asking. It's run in slices so new input can be
+ * handed over, and one that has read the default IDLE_POLLS times in a
+ * row without being given anything or outputting counts as idle and
+ * reports itself blocked, but carries on polling.
+ */
+pub(crate) struct Machine {
+    computer: Intcode<Counter>,
+    queue: VecDeque<i64>,
+    policy: InputPolicy,
+    consumed: u64,
+    empty_reads: u64,
+    blocked: bool,
+    halted: bool,
+    blocked_since: Option<Instant>,
+    waited: Duration,
+    running: Duration,
+}
+
+
+impl Machine {
+    pub(crate) fn new(prog: &[i64], empty: Option<i64>, boot: Vec<i64>) -> Machine {
+        let policy = match empty {
+            Some(i) => InputPolicy::Default(i),
+            None => InputPolicy::Block
+        };
+
+        Machine {
+            computer: Intcode::new(prog).observe(Counter::new()),
+            queue: boot.into(),
+            policy,
+            consumed: 0,
+            empty_reads: 0,
+            blocked: false,
+            halted: false,
+            blocked_since: None,
+            waited: Duration::ZERO,
+            running: Duration::ZERO,
+        }
+    }
+
+    fn polls(&self) -> bool {
+        self.policy != InputPolicy::Block
+    }
+
+    /*
+     * Runs until the machine outputs, blocks or halts. None if it can't
+     * run, having halted or blocked with nothing new to read, or if it's
+     * idle and spent another slice polling.
+     */
+    fn step(&mut self) -> Option<Event> {
+        if self.halted || (self.blocked && self.queue.is_empty() && ! self.polls()) {
+            return None;
+        }
+
+        if ! self.queue.is_empty() {
+            self.wake();
+        }
+
+        let idle = self.blocked;
+        let started = Instant::now();
+        let event = self.advance();
+
+        // polling while idle counts as time blocked, not running
+        if ! (idle && self.blocked) {
+            self.running += started.elapsed();
+        }
+
+        if idle && ! self.blocked {
+            self.wake();
+        } else if ! idle && self.blocked {
+            self.blocked_since = Some(Instant::now());
+        }
+
+        event
+    }
+
+    fn wake(&mut self) {
+        if let Some(since) = self.blocked_since.take() {
+            self.waited += since.elapsed();
+        }
+
+        self.blocked = false;
+    }
+
+    fn advance(&mut self) -> Option<Event> {
+        loop {
+            // queued values are handed over one at a time as they're asked
+            // for, the default is only read once there are none
+            let policy = if self.queue.is_empty() { self.policy } else { InputPolicy::Block };
+            let reads = self.computer.default_reads();
+
+            self.computer.set_input_policy(policy);
+
+            let response = match policy {
+                InputPolicy::Block => Some(self.computer.run()),
+                InputPolicy::Default(_) => self.computer.run_for(SLICE)
+            };
+
+            match response {
+                Some(IntResponse::Output(i)) => {
+                    self.empty_reads = 0;
+                    self.blocked = false;
+                    return Some(Event::Output(i));
+                },
+                Some(IntResponse::Halt) => {
+                    self.blocked = false;
+                    self.halted = true;
+                    return Some(Event::Halt);
+                },
+                Some(IntResponse::Input) => match self.queue.pop_front() {
+                    Some(i) => {
+                        self.computer.input(i);
+                        self.consumed += 1;
+                        self.empty_reads = 0;
+                    },
+                    None => return self.block()
+                },
+                None => {
+                    self.empty_reads += self.computer.default_reads() - reads;
+
+                    if self.empty_reads >= IDLE_POLLS {
+                        return self.block();
                     }
                 }
-            });
+            };
+        }
+    }
 
-            senders.push(to_tx);
-            receivers.push(from_rx);
+    /*
+     * Blocked event for a machine that has just run out of input, None if
+     * it already had
+     */
+    fn block(&mut self) -> Option<Event> {
+        if self.blocked {
+            return None;
         }
 
+        self.blocked = true;
+
+        Some(Event::Blocked(self.consumed, self.computer.ip()))
+    }
+
+    /*
+     * Metrics so far, not counting any wait still going on
+     */
+    fn stats(&self) -> (Metrics, Option<Instant>) {
+        let counter = self.computer.observer();
+
+        let metrics = Metrics {
+            instructions: counter.instructions,
+            inputs: counter.inputs,
+            outputs: counter.outputs,
+            blocked: self.waited,
+            running: self.running,
+        };
+
+        (metrics, self.blocked_since)
+    }
+}
+
+
+/*
+ * Runs a machine on its own thread until it halts or the MultiCoder goes
+ * away. Values come in batches so a packet's x and y arrive together.
+ */
+pub(crate) fn worker(mut machine: Machine, id: usize, inputs: Receiver<Vec<i64>>, events: Sender<(usize, Event)>, stats: Stats) {
+    loop {
+        while let Ok(values) = inputs.try_recv() {
+            machine.queue.extend(values);
+        }
+
+        match machine.step() {
+            Some(event) => {
+                *stats.lock().unwrap() = machine.stats();
+
+                if events.send((id, event)).is_err() || event == Event::Halt {
+                    return;
+                }
+            },
+            // an idle machine keeps polling, but gives new input a moment
+            // to arrive between slices rather than spinning
+            None if machine.polls() => match inputs.recv_timeout(IDLE_WAIT) {
+                Ok(values) => machine.queue.extend(values),
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => return
+            },
+            None => match inputs.recv() {
+                Ok(values) => machine.queue.extend(values),
+                Err(_) => return
+            }
+        };
+    }
+}
+
+
+impl MultiCoder {
+
+    pub fn new(prog: &[i64], size: usize) -> MultiCoder {
+        MultiCoder::with_programs(&vec!(prog; size))
+    }
+
+    /*
+     * One machine for each program, numbered in order
+     */
+    pub fn with_programs(progs: &[&[i64]]) -> MultiCoder {
+        MultiCoder::scheduled(progs, Schedule::Threads)
+    }
+
+    pub fn scheduled(progs: &[&[i64]], schedule: Schedule) -> MultiCoder {
+        MultiCoder::start(progs, None, vec!(Vec::new(); progs.len()), schedule)
+    }
+
+    /*
+     * Machines on a packet network, each booted with its address. They
+     * output packets as dest, x, y and read -1 when nothing is waiting.
+     * Drive it with nat.
+     */
+    pub fn network(prog: &[i64], size: usize) -> MultiCoder {
+        MultiCoder::scheduled_network(prog, size, Schedule::Threads)
+    }
+
+    pub fn scheduled_network(prog: &[i64], size: usize, schedule: Schedule) -> MultiCoder {
+        let addresses = (0..size as i64).map(|a| vec!(a)).collect();
+
+        MultiCoder::start(&vec!(prog; size), Some(-1), addresses, schedule)
+    }
+
+    /*
+     * A machine for each program, with boot values queued before it runs
+     * so it can't poll ahead of them
+     */
+    fn start(progs: &[&[i64]], empty: Option<i64>, boot: Vec<Vec<i64>>, schedule: Schedule) -> MultiCoder {
+        let booted = boot.clone();
+        let stats: Vec<Stats> = progs.iter().map(|_| Stats::default()).collect();
+
+        let backend = match schedule {
+            Schedule::Threads => {
+                let (events_tx, events) = channel();
+                let mut inputs = Vec::new();
+
+                for (id, (prog, boot)) in progs.iter().zip(boot).enumerate() {
+                    let (input_tx, input_rx) = channel();
+                    let machine = Machine::new(prog, empty, boot);
+                    let events_tx = events_tx.clone();
+                    let stats = stats[id].clone();
+
+                    thread::spawn(move || worker(machine, id, input_rx, events_tx, stats));
+
+                    inputs.push(input_tx);
+                }
+
+                Backend::Threads { inputs, events }
+            },
+            Schedule::RoundRobin => Backend::RoundRobin {
+                machines: progs.iter().zip(boot).map(|(prog, boot)| Machine::new(prog, empty, boot)).collect(),
+                current: 0,
+            }
+        };
+
+        MultiCoder::around(backend, booted, stats)
+    }
+
+    /*
+     * Each machine in a process of its own, started by running exe with
+     * args, talking to this one over a Unix socket. exe has to end up in
+     * remote::node, like `three node` does. Nodes are killed when the
+     * MultiCoder goes, if they haven't halted by then.
+     */
+    pub fn processes(exe: &Path, args: &[&str], progs: &[&[i64]]) -> io::Result<MultiCoder> {
+        let boot = vec!(Vec::new(); progs.len());
+        let launched = remote::launch(exe, args, progs, None, boot.clone())?;
+
+        let backend = Backend::Processes {
+            inputs: launched.inputs,
+            events: launched.events,
+            children: launched.children,
+            errors: launched.errors,
+        };
+
+        Ok(MultiCoder::around(backend, boot, launched.stats))
+    }
+
+    fn around(backend: Backend, boot: Vec<Vec<i64>>, stats: Vec<Stats>) -> MultiCoder {
+        let size = boot.len();
+        let sent = boot.iter().map(|b| b.len() as u64).collect();
+
         MultiCoder {
-            senders: senders,
-            receivers: receivers
+            backend,
+            pending: vec!(VecDeque::new(); size),
+            routes: vec!(Vec::new(); size),
+            sent,
+            halted: vec!(false; size),
+            waiting: vec!(None; size),
+            boot,
+            capture: None,
+            stats,
         }
     }
 
-    pub fn send(&mut self, comp: usize, input: i64) -> i64 {
-        self.senders[comp].send(IntResponse::Output(input)).unwrap();
+    pub fn len(&self) -> usize {
+        self.halted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.halted.is_empty()
+    }
+
+    /*
+     * Queue input for a machine and wait for what it does next
+     */
+    pub fn send(&mut self, comp: usize, input: i64) -> IntResponse {
+        self.input(comp, input);
 
         self.output(comp)
     }
 
+    /*
+     * Queue input for a machine without waiting on it
+     */
+    pub fn input(&mut self, comp: usize, input: i64) {
+        self.input_all(comp, &[input]);
+    }
+
+    /*
+     * Queue several inputs for a machine, all arriving at once
+     */
+    pub fn input_all(&mut self, comp: usize, values: &[i64]) {
+        self.deliver(None, comp, values);
+    }
+
+    /*
+     * Queues values for a machine, logging them if capturing
+     */
+    fn deliver(&mut self, from: Option<usize>, to: usize, values: &[i64]) {
+        if let Some(capture) = &mut self.capture {
+            for value in values {
+                capture.record(from, to, *value);
+            }
+        }
+
+        match &mut self.backend {
+            // a machine that has halted has dropped its queue, nothing to do
+            Backend::Threads { inputs, .. } | Backend::Processes { inputs, .. } => {
+                let _ = inputs[to].send(values.to_vec());
+            },
+            Backend::RoundRobin { machines, .. } => machines[to].queue.extend(values)
+        };
+
+        self.sent[to] += values.len() as u64;
+        self.waiting[to] = None;
+    }
+
+    /*
+     * Send every value one machine outputs from now on to another's input,
+     * as well as anywhere else it already goes
+     */
     pub fn connect(&mut self, from: usize, to: usize) {
-        for input in &self.receivers[from] {
-            self.senders[to].send(input);
+        self.routes[from].push(to);
+    }
+
+    /*
+     * Connect each machine to the next
+     */
+    pub fn chain(&mut self) {
+        for i in 1..self.len() {
+            self.connect(i - 1, i);
+        }
+    }
+
+    /*
+     * Connect each machine to the next and the last back to the first
+     */
+    pub fn ring(&mut self) {
+        self.chain();
+
+        if ! self.is_empty() {
+            self.connect(self.len() - 1, 0);
+        }
+    }
+
+    /*
+     * Starts logging every value delivered to a machine, whether from
+     * outside or from another machine. Values machines were booted with
+     * come first, as sent from outside.
+     */
+    pub fn capture(&mut self) {
+        let mut capture = Capture::new();
+
+        for (to, values) in self.boot.iter().enumerate() {
+            for value in values {
+                capture.record(None, to, *value);
+            }
+        }
+
+        self.capture = Some(capture);
+    }
+
+    pub fn captured(&self) -> Option<&Capture> {
+        self.capture.as_ref()
+    }
+
+    /*
+     * Runs the network until every machine halts, returning what each one
+     * output that hasn't already been taken with output or send
+     */
+    pub fn run(&mut self) -> Vec<Vec<i64>> {
+        let mut outputs = vec!(Vec::new(); self.len());
+
+        for (comp, out) in outputs.iter_mut().enumerate() {
+            while let Some(event) = self.event(comp) {
+                if let Event::Output(i) = event {
+                    out.push(i);
+                }
+            }
+        }
+
+        outputs
+    }
+
+    /*
+     * Metrics for each machine. Machines in other processes send theirs
+     * when they block or halt, so those are as of then.
+     */
+    pub fn metrics(&self) -> Vec<Metrics> {
+        self.stats.iter().map(|stats| {
+            let (mut metrics, blocked_since) = *stats.lock().unwrap();
+
+            if let Some(since) = blocked_since {
+                metrics.blocked += since.elapsed();
+            }
+
+            metrics
+        }).collect()
+    }
+
+    /*
+     * The metrics as a table, a row per machine, the one that spent
+     * longest running marked as the bottleneck
+     */
+    pub fn summary(&self) -> String {
+        let metrics = self.metrics();
+        let busiest = metrics.iter().enumerate().max_by_key(|(_, m)| m.running).map(|(id, _)| id);
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+
+        let mut table = format!("{:>4} {:>12} {:>8} {:>8} {:>12} {:>12}\n",
+                                "node", "instructions", "inputs", "outputs", "blocked ms", "running ms");
+
+        for (id, m) in metrics.iter().enumerate() {
+            table += &format!("{:>4} {:>12} {:>8} {:>8} {:>12.3} {:>12.3}{}\n",
+                              id, m.instructions, m.inputs, m.outputs, ms(m.blocked), ms(m.running),
+                              if Some(id) == busiest { "  <- bottleneck" } else { "" });
+        }
+
+        table
+    }
+
+    /*
+     * Waits for a machine to output, halt, or block having read everything
+     * sent to it. Input when the whole network is deadlocked too.
+     */
+    pub fn output(&mut self, comp: usize) -> IntResponse {
+        while let Some(event) = self.event(comp) {
+            match event {
+                Event::Output(i) => return IntResponse::Output(i),
+                Event::Blocked(consumed, _) if consumed < self.sent[comp] => continue,
+                Event::Blocked(_, _) => return IntResponse::Input,
+                Event::Halt => return IntResponse::Halt
+            };
+        }
+
+        match self.halted[comp] {
+            true => IntResponse::Halt,
+            false => IntResponse::Input
+        }
+    }
+
+    /*
+     * Why a machine in another process stopped, if it didn't halt: the
+     * machine panicked, or the process died or broke protocol. Such
+     * machines count as halted.
+     */
+    pub fn error(&self, comp: usize) -> Option<String> {
+        match &self.backend {
+            Backend::Processes { errors, .. } => errors.lock().unwrap()[comp].clone(),
+            _ => None
+        }
+    }
+
+    /*
+     * Whether a machine is known to have halted. One that has but hasn't
+     * been heard from yet still counts as running.
+     */
+    pub fn halted(&self, comp: usize) -> bool {
+        self.halted[comp]
+    }
+
+    /*
+     * Next event from a machine, keeping any from the others for later.
+     * None once it has halted and every event from it has been seen.
+     */
+    fn event(&mut self, comp: usize) -> Option<Event> {
+        if let Some(event) = self.pending[comp].pop_front() {
+            return Some(event);
+        }
+
+        while ! self.halted[comp] {
+            let (id, event) = self.receive()?;
+
+            if id == comp {
+                return Some(event);
+            }
+
+            self.pending[id].push_back(event);
+        }
+
+        None
+    }
+
+    /*
+     * Next event from any machine, first any kept for later
+     */
+    fn next_event(&mut self) -> Option<(usize, Event)> {
+        for (id, pending) in self.pending.iter_mut().enumerate() {
+            if let Some(event) = pending.pop_front() {
+                return Some((id, event));
+            }
         }
+
+        self.receive()
     }
 
-    pub fn output(&mut self, comp: usize) -> i64 {
-        match self.receivers[comp].recv() {
-            Ok(i) => match i {
-                IntResponse::Output(i) => i,
-                _ => -1
+    /*
+     * Waits for the next thing a machine does, noting halts and passing
+     * outputs along its routes. Under round robin that's running machines
+     * in turn from wherever the last one stopped. None once nothing more
+     * can happen, all the machines having halted or deadlocked.
+     */
+    fn receive(&mut self) -> Option<(usize, Event)> {
+        if self.deadlock().is_some() {
+            return None;
+        }
+
+        let (id, event) = match &mut self.backend {
+            Backend::Threads { events, .. } | Backend::Processes { events, .. } => events.recv().ok()?,
+            Backend::RoundRobin { machines, current } => {
+                let mut next = None;
+
+                for _ in 0..machines.len() {
+                    let id = *current;
+
+                    match machines[id].step() {
+                        Some(event) => {
+                            *self.stats[id].lock().unwrap() = machines[id].stats();
+
+                            // a machine keeps its turn until it stops running
+                            if ! matches!(event, Event::Output(_)) {
+                                *current = (id + 1) % machines.len();
+                            }
+                            next = Some((id, event));
+                            break;
+                        },
+                        None => *current = (id + 1) % machines.len()
+                    };
+                }
+
+                next?
+            }
+        };
+
+        self.waiting[id] = None;
+
+        match event {
+            Event::Halt => self.halted[id] = true,
+            Event::Output(i) => for to in self.routes[id].clone() {
+                self.deliver(Some(id), to, &[i]);
             },
-            _ => -1
+            Event::Blocked(consumed, ip) => if consumed == self.sent[id] {
+                self.waiting[id] = Some(ip);
+            }
+        };
+
+        Some((id, event))
+    }
+
+    /*
+     * Whether the network is stuck, with every machine halted or blocked
+     * having read everything sent to it, and at least one of them blocked.
+     * Nothing more can happen until more input is sent from outside.
+     */
+    pub fn deadlock(&self) -> Option<Deadlock> {
+        let stuck = (0..self.len()).all(|id| self.halted[id] || self.waiting[id].is_some());
+
+        if ! stuck || self.waiting.iter().all(|w| w.is_none()) {
+            return None;
         }
+
+        let nodes = (0..self.len()).map(|id| NodeState {
+            ip: self.waiting[id],
+            pending: self.pending[id].iter().filter_map(|e| match e {
+                Event::Output(i) => Some(*i),
+                _ => None
+            }).collect(),
+        }).collect();
+
+        Some(Deadlock { nodes })
     }
 
-    pub fn feedback(&mut self, start: i64) -> i64 {
-        let mut answer = self.send(0, start);
-        let mut final_answer = 0;
-        let mut index = 1;
+    /*
+     * Runs a packet network, delivering each packet to its machine. The
+     * NAT keeps the last packet sent to it, and whenever every machine is
+     * idle with nothing left to read it sends that packet on to machine 0.
+     * A polling machine is idle once it has read the default IDLE_POLLS
+     * times in a row without receiving or sending anything.
+     * Returns once it sends the same y twice in a row, or None if the
+     * machines halt before then.
+     */
+    pub fn nat(&mut self) -> Option<NatReport> {
+        let mut partial = vec!(Vec::new(); self.len());
+        let mut idle = vec!(false; self.len());
+        let mut first = None;
+        let mut last: Option<Packet> = None;
+        let mut woken = None;
+
+        while let Some((id, event)) = self.next_event() {
+            match event {
+                Event::Output(i) => {
+                    idle[id] = false;
+                    partial[id].push(i);
+
+                    if partial[id].len() == 3 {
+                        let packet = Packet { dest: partial[id][0] as usize, x: partial[id][1], y: partial[id][2] };
+                        partial[id].clear();
+
+                        if packet.dest == NAT {
+                            first.get_or_insert(packet.y);
+                            last = Some(packet);
+                        } else if packet.dest < self.len() {
+                            self.deliver(Some(id), packet.dest, &[packet.x, packet.y]);
+                            idle[packet.dest] = false;
+                        }
+                    }
+                },
+                Event::Blocked(consumed, _) => idle[id] = consumed == self.sent[id],
+                Event::Halt => idle[id] = true
+            };
+
+            if ! idle.iter().all(|i| *i) {
+                continue;
+            }
+
+            let packet = last?;
+
+            if woken == Some(packet.y) {
+                return Some(NatReport { first: first?, repeated: packet.y });
+            }
+
+            woken = Some(packet.y);
+            self.deliver(Some(NAT), 0, &[packet.x, packet.y]);
+            idle[0] = false;
+        }
+
+        None
+    }
 
-        while answer > 0 {
-            answer = self.send(index, answer);
+    /*
+     * Passes a signal around the machines in order, each one's output the
+     * next one's input, until the last halts. A machine only gets an input
+     * when the one before it has just output, and one that has halted gets
+     * nothing, what it would have been given is dropped. Returns the
+     * last signal out of the last machine, None if it never output or the
+     * signal got stuck with a machine waiting on more input than it was
+     * given.
+     */
+    pub fn feedback(&mut self, start: i64) -> Option<i64> {
+        let last = self.len().checked_sub(1)?;
+        let mut signal = Some((None, start));
+        let mut answer = None;
+        let mut index = 0;
 
-            if answer > 0 {
-                final_answer = answer;
+        loop {
+            if let Some((from, value)) = signal.take() {
+                if ! self.halted[index] {
+                    self.deliver(from, index, &[value]);
+                }
             }
 
-            index = (index + 1) % self.senders.len();
+            match self.output(index) {
+                IntResponse::Output(i) => {
+                    signal = Some((Some(index), i));
+
+                    if index == last {
+                        answer = Some(i);
+                    }
+                },
+                IntResponse::Halt if index == last => return answer,
+                IntResponse::Halt => {},
+                IntResponse::Input => return None
+            };
+
+            index = (index + 1) % self.len();
+        }
+    }
+
+}
+
+
+impl Drop for MultiCoder {
+    fn drop(&mut self) {
+        if let Backend::Processes { children, .. } = &mut self.backend {
+            for child in children.iter_mut() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
         }
+    }
+}
+
+
+/*
+ * Signal out of the last amplifier once a network of prog, one for each
+ * phase, has run to the end after 0 is fed into the first
+ */
+pub fn amplify(prog: &[i64], phases: &[i64], topology: Topology) -> Option<i64> {
+    let mut mcoder = MultiCoder::scheduled(&vec!(prog; phases.len()), Schedule::RoundRobin);
 
-        final_answer
+    match topology {
+        Topology::Chain => mcoder.chain(),
+        Topology::Feedback => mcoder.ring()
+    };
+
+    for (i, p) in phases.iter().enumerate() {
+        mcoder.input(i, *p);
     }
+    mcoder.input(0, 0);
 
+    mcoder.run().last()?.last().copied()
+}
+
+
+/*
+ * Tries every order of the phases across a pool of threads and returns
+ * the one giving the strongest signal. Ties go to the order the
+ * permutations are generated in, so the answer doesn't depend on which
+ * thread gets there first.
+ */
+pub fn best_phases(prog: &[i64], phases: Range<i64>, topology: Topology, threads: usize) -> Option<Tuning> {
+    let mut settings: Vec<i64> = phases.collect();
+    let queue = Mutex::new(Heap::new(&mut settings).enumerate());
+    let best: Mutex<Option<(i64, usize, Vec<i64>)>> = Mutex::new(None);
+
+    thread::scope(|s| {
+        for _ in 0..threads.max(1) {
+            s.spawn(|| loop {
+                let (index, order) = match queue.lock().unwrap().next() {
+                    Some(next) => next,
+                    None => return
+                };
+
+                let signal = match amplify(prog, &order, topology) {
+                    Some(signal) => signal,
+                    None => continue
+                };
+
+                let mut best = best.lock().unwrap();
+                let better = best.as_ref().is_none_or(|(s, i, _)| signal > *s || (signal == *s && index < *i));
+
+                if better {
+                    *best = Some((signal, index, order));
+                }
+            });
+        }
+    });
+
+    best.into_inner().unwrap().map(|(signal, _, phases)| Tuning { phases, signal })
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::capture::Message;
+    use crate::compiler;
 
     #[test]
     fn test_normal1() {
         let prog = vec!(3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0);
-        let phase = vec!(4,3,2,1,0);
+        let phase = [4,3,2,1,0];
         let size = phase.len();
         let mut mcoder = MultiCoder::new(&prog, size);
 
@@ -104,14 +1006,14 @@ mod tests {
 
         let answer = mcoder.feedback(0);
 
-        assert_eq!(answer, 43210);
+        assert_eq!(answer, Some(43210));
     }
 
     #[test]
     fn test_normal2() {
         let prog = vec!(3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,
                         1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0);
-        let phase = vec!(1,0,4,3,2);
+        let phase = [1,0,4,3,2];
         let size = phase.len();
         let mut mcoder = MultiCoder::new(&prog, size);
 
@@ -121,7 +1023,7 @@ mod tests {
 
         let answer = mcoder.feedback(0);
 
-        assert_eq!(answer, 65210);
+        assert_eq!(answer, Some(65210));
     }
 
 
@@ -129,7 +1031,7 @@ mod tests {
     fn test_feedback1() {
         let prog = vec!(3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,
                         4,27,1001,28,-1,28,1005,28,6,99,0,0,5);
-        let phase = vec!(9,8,7,6,5);
+        let phase = [9,8,7,6,5];
         let mut mcoder = MultiCoder::new(&prog, phase.len());
 
         for (i, p) in phase.iter().enumerate() {
@@ -137,7 +1039,7 @@ mod tests {
         }
 
         let answer = mcoder.feedback(0);
-        assert_eq!(answer, 139629729);
+        assert_eq!(answer, Some(139629729));
     }
 
     #[test]
@@ -145,7 +1047,7 @@ mod tests {
         let prog = vec!(3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,
                         -5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,
                         53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10);
-        let phase = vec!(9,7,8,5,6);
+        let phase = [9,7,8,5,6];
         let mut mcoder = MultiCoder::new(&prog, phase.len());
 
         for (i, p) in phase.iter().enumerate() {
@@ -153,7 +1055,360 @@ mod tests {
         }
 
         let answer = mcoder.feedback(0);
-        assert_eq!(answer, 18216);
+        assert_eq!(answer, Some(18216));
+    }
+
+    #[test]
+    fn test_chain() {
+        let prog = vec!(3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0);
+        let mut mcoder = MultiCoder::new(&prog, 5);
+        mcoder.chain();
+
+        for (i, p) in [4,3,2,1,0].iter().enumerate() {
+            mcoder.input(i, *p);
+        }
+        mcoder.input(0, 0);
+
+        let outputs = mcoder.run();
+        assert_eq!(outputs[4], vec!(43210));
+        assert_eq!(outputs[0], vec!(4));
     }
 
+    #[test]
+    fn test_ring() {
+        let prog = vec!(3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,
+                        4,27,1001,28,-1,28,1005,28,6,99,0,0,5);
+        let mut mcoder = MultiCoder::new(&prog, 5);
+        mcoder.ring();
+
+        for (i, p) in [9,8,7,6,5].iter().enumerate() {
+            mcoder.input(i, *p);
+        }
+        mcoder.input(0, 0);
+
+        assert_eq!(mcoder.run()[4].last(), Some(&139629729));
+    }
+
+    #[test]
+    fn test_network() {
+        // output double each input, halting after 0
+        let double = vec!(3,20,1002,20,2,21,4,21,1005,20,0,99);
+        // sum inputs, output the total after the second 0
+        let sum = vec!(3,30,1,31,30,31,1005,30,0,1001,32,1,32,1008,32,2,33,1006,33,0,4,31,99);
+
+        // 0 fans out to 1 and 2, which fan back in to 3
+        let mut mcoder = MultiCoder::with_programs(&[&double, &double, &double, &sum]);
+        mcoder.connect(0, 1);
+        mcoder.connect(0, 2);
+        mcoder.connect(1, 3);
+        mcoder.connect(2, 3);
+
+        mcoder.input(0, 3);
+        mcoder.input(0, 5);
+        mcoder.input(0, 0);
+
+        assert_eq!(mcoder.run(), vec!(vec!(6,10,0), vec!(12,20,0), vec!(12,20,0), vec!(64)));
+    }
+
+    #[test]
+    fn test_nat() {
+        // passes packets along the line adding 1 to y, the last machine
+        // sends them to the NAT with y capped at 5
+        let text = "
+            fn main() {
+                let addr = input();
+                if addr == 0 { output(1); output(7); output(0); }
+                while 1 {
+                    let x = input();
+                    if x != -1 {
+                        let y = input();
+                        if addr == 2 {
+                            if y > 5 { y = 5; }
+                            output(255); output(x); output(y);
+                        } else {
+                            output(addr + 1); output(x); output(y + 1);
+                        }
+                    }
+                }
+            }
+        ";
+        let prog = compiler::compile(text, "nat.c").unwrap();
+        let mut mcoder = MultiCoder::network(&prog, 3);
+        assert_eq!(mcoder.nat(), Some(NatReport { first: 1, repeated: 5 }));
+
+        let mut mcoder = MultiCoder::scheduled_network(&prog, 3, Schedule::RoundRobin);
+        assert_eq!(mcoder.nat(), Some(NatReport { first: 1, repeated: 5 }));
+    }
+
+    #[test]
+    fn test_nat_polling() {
+        // 1 polls 20 times before sending anything, 0 passes whatever it
+        // gets on to the NAT
+        let text = "
+            fn main() {
+                let addr = input();
+                let polls = 0;
+                while 1 {
+                    let x = input();
+                    if x == -1 {
+                        polls = polls + 1;
+                        if addr == 1 {
+                            if polls == 20 { output(0); output(1); output(polls); }
+                        }
+                    } else {
+                        let y = input();
+                        output(255); output(x); output(y);
+                    }
+                }
+            }
+        ";
+        let prog = compiler::compile(text, "poll.c").unwrap();
+
+        for schedule in [Schedule::Threads, Schedule::RoundRobin].iter() {
+            let mut mcoder = MultiCoder::scheduled_network(&prog, 2, *schedule);
+            assert_eq!(mcoder.nat(), Some(NatReport { first: 20, repeated: 20 }));
+        }
+    }
+
+    #[test]
+    fn test_idle() {
+        // polls once, then counts down for 1400 instructions before sending
+        let busy = vec!(3,100,1101,700,0,101,1001,101,-1,101,1005,101,6,4,101,99);
+        let mut machine = Machine::new(&busy, Some(-1), Vec::new());
+        assert_eq!(machine.step(), Some(Event::Output(0)));
+
+        // polls forever
+        let poll = vec!(3,100,1105,1,0);
+        let mut machine = Machine::new(&poll, Some(-1), Vec::new());
+        assert!(matches!(machine.step(), Some(Event::Blocked(0, _))));
+        assert_eq!(machine.step(), None);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let double = vec!(3,20,1002,20,2,21,4,21,1005,20,0,99);
+
+        // 3 halts on the first 0 it reads, so what it outputs depends on
+        // how its inputs from 1 and 2 interleave
+        let run = || {
+            let mut mcoder = MultiCoder::scheduled(&[&double[..]; 4], Schedule::RoundRobin);
+            mcoder.connect(0, 1);
+            mcoder.connect(0, 2);
+            mcoder.connect(1, 3);
+            mcoder.connect(2, 3);
+            mcoder.input_all(0, &[3, 5, 0]);
+            mcoder.run()
+        };
+
+        let expected = vec!(vec!(6,10,0), vec!(12,20,0), vec!(12,20,0), vec!(24,40,0));
+        for _ in 0..5 {
+            assert_eq!(run(), expected);
+        }
+
+        let prog = vec!(3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,
+                        4,27,1001,28,-1,28,1005,28,6,99,0,0,5);
+        let mut mcoder = MultiCoder::scheduled(&[&prog[..]; 5], Schedule::RoundRobin);
+        for (i, p) in [9,8,7,6,5].iter().enumerate() {
+            mcoder.send(i, *p);
+        }
+        assert_eq!(mcoder.feedback(0), Some(139629729));
+    }
+
+    #[test]
+    fn test_best_phases() {
+        let prog = vec!(3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0);
+        assert_eq!(amplify(&prog, &[4,3,2,1,0], Topology::Chain), Some(43210));
+        assert_eq!(best_phases(&prog, 0..5, Topology::Chain, 4),
+                   Some(Tuning { phases: vec!(4,3,2,1,0), signal: 43210 }));
+
+        let prog = vec!(3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,
+                        1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0);
+        assert_eq!(best_phases(&prog, 0..5, Topology::Chain, 3),
+                   Some(Tuning { phases: vec!(1,0,4,3,2), signal: 65210 }));
+
+        let prog = vec!(3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,
+                        4,27,1001,28,-1,28,1005,28,6,99,0,0,5);
+        assert_eq!(best_phases(&prog, 5..10, Topology::Feedback, 4),
+                   Some(Tuning { phases: vec!(9,8,7,6,5), signal: 139629729 }));
+
+        let prog = vec!(3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,
+                        -5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,
+                        53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10);
+        assert_eq!(best_phases(&prog, 5..10, Topology::Feedback, 1),
+                   Some(Tuning { phases: vec!(9,7,8,5,6), signal: 18216 }));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let double = vec!(3,20,1002,20,2,21,4,21,1005,20,0,99);
+
+        for schedule in [Schedule::Threads, Schedule::RoundRobin] {
+            // 0 and 1 halt, 2 never hears from anyone
+            let mut mcoder = MultiCoder::scheduled(&[&double[..]; 3], schedule);
+            mcoder.connect(0, 1);
+            mcoder.input_all(0, &[3, 0]);
+
+            assert_eq!(mcoder.run(), vec!(vec!(6,0), vec!(12,0), vec!()));
+
+            let deadlock = mcoder.deadlock().unwrap();
+            assert_eq!(deadlock.nodes, vec!(
+                NodeState { ip: None, pending: vec!() },
+                NodeState { ip: None, pending: vec!() },
+                NodeState { ip: Some(0), pending: vec!() },
+            ));
+
+            // everyone in a ring waiting on the one before
+            let mut mcoder = MultiCoder::scheduled(&[&double[..]; 3], schedule);
+            mcoder.ring();
+            assert_eq!(mcoder.output(1), IntResponse::Input);
+            assert_eq!(mcoder.run(), vec!(vec!(); 3));
+
+            // sending more gets it going again until it's stuck once more
+            mcoder.input(2, 5);
+            assert_eq!(mcoder.output(2), IntResponse::Output(10));
+            assert_eq!(mcoder.output(0), IntResponse::Output(20));
+            assert_eq!(mcoder.output(1), IntResponse::Output(40));
+            assert_eq!(mcoder.output(2), IntResponse::Output(80));
+            assert!(mcoder.deadlock().is_none());
+        }
+
+        let mut mcoder = MultiCoder::scheduled(&[&double[..]; 2], Schedule::RoundRobin);
+        mcoder.input(0, 1);
+        mcoder.input(1, 0);
+        assert_eq!(mcoder.output(1), IntResponse::Output(0));
+        assert_eq!(mcoder.output(1), IntResponse::Halt);
+        assert!(mcoder.halted(1) && ! mcoder.halted(0));
+        assert_eq!(mcoder.deadlock().unwrap().to_string(),
+                   "deadlock, every machine is halted or waiting on input\n  0: waiting at 0, pending outputs [2]\n  1: halted");
+    }
+
+    #[test]
+    fn test_feedback_signs() {
+        // subtracts 5 from each of three inputs, then halts
+        let prog = vec!(3,20,1001,20,-5,20,4,20,1001,21,-1,21,1005,21,0,99,0,0,0,0,0,3);
+        let mut mcoder = MultiCoder::new(&prog, 2);
+
+        assert_eq!(mcoder.feedback(7), Some(-23));
+        assert!(mcoder.halted(0) && mcoder.halted(1));
+        assert_eq!(mcoder.send(0, 1), IntResponse::Halt);
+
+        // the last machine never gets to output
+        let mut mcoder = MultiCoder::new(&prog, 2);
+        assert_eq!(mcoder.send(1, 1), IntResponse::Output(-4));
+        assert_eq!(mcoder.send(1, 1), IntResponse::Output(-4));
+        assert_eq!(mcoder.send(1, 1), IntResponse::Output(-4));
+        assert_eq!(mcoder.feedback(0), None);
+    }
+
+    #[test]
+    fn test_feedback_halted() {
+        // the first adds 1 to two inputs, the middle multiplies one by 10,
+        // and the last passes two on
+        let first = vec!(3,30,1001,30,1,30,4,30,3,30,1001,30,1,30,4,30,99);
+        let middle = vec!(3,30,1002,30,10,30,4,30,99);
+        let last = vec!(3,30,4,30,3,30,4,30,99);
+
+        let mut mcoder = MultiCoder::scheduled(&[&first, &middle, &last], Schedule::RoundRobin);
+        mcoder.capture();
+
+        // the middle has halted by the time 11 comes round, so the last
+        // never gets a second input
+        assert_eq!(mcoder.feedback(0), None);
+
+        let capture = mcoder.captured().unwrap();
+        assert_eq!(capture.inputs(0), vec!(0, 10));
+        assert_eq!(capture.inputs(1), vec!(1));
+        assert_eq!(capture.inputs(2), vec!(10));
+    }
+
+    #[test]
+    fn test_capture() {
+        let prog = vec!(3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,
+                        4,27,1001,28,-1,28,1005,28,6,99,0,0,5);
+        let mut mcoder = MultiCoder::new(&prog, 5);
+        mcoder.ring();
+        mcoder.capture();
+
+        for (i, p) in [9,8,7,6,5].iter().enumerate() {
+            mcoder.input(i, *p);
+        }
+        mcoder.input(0, 0);
+        let outputs = mcoder.run();
+
+        let capture = mcoder.captured().unwrap();
+        let messages = &capture.messages;
+        assert_eq!(messages[5], Message { seq: 5, from: None, to: 0, value: 0 });
+        assert!(messages[6..].iter().all(|m| m.from == Some((m.to + 4) % 5)));
+
+        // each amplifier alone does just what it did in the ring
+        for (node, output) in outputs.iter().enumerate() {
+            assert_eq!(&capture.replay(&prog, node).1, output);
+        }
+
+        let path = std::env::temp_dir().join(format!("capture-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        capture.save(path).unwrap();
+        assert_eq!(&Capture::open(path).unwrap(), capture);
+        std::fs::remove_file(path).unwrap();
+
+        // packet networks log who each packet came from, NAT included
+        let text = "
+            fn main() {
+                let addr = input();
+                if addr == 0 { output(1); output(7); output(0); }
+                while 1 {
+                    let x = input();
+                    if x != -1 {
+                        let y = input();
+                        if addr == 1 { output(255); output(x); output(5); }
+                        else { output(1); output(x); output(y); }
+                    }
+                }
+            }
+        ";
+        let prog = compiler::compile(text, "nat.c").unwrap();
+        let mut mcoder = MultiCoder::scheduled_network(&prog, 2, Schedule::RoundRobin);
+        mcoder.capture();
+        assert_eq!(mcoder.nat(), Some(NatReport { first: 5, repeated: 5 }));
+
+        let sources: Vec<Option<usize>> = mcoder.captured().unwrap().messages.iter().map(|m| m.from).collect();
+        assert_eq!(sources, vec!(None, None, Some(0), Some(0), Some(NAT), Some(NAT), Some(0), Some(0)));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let prog = vec!(3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0);
+
+        for schedule in [Schedule::Threads, Schedule::RoundRobin] {
+            let mut mcoder = MultiCoder::scheduled(&[&prog[..]; 5], schedule);
+            mcoder.chain();
+
+            for (i, p) in [4,3,2,1,0].iter().enumerate() {
+                mcoder.input(i, *p);
+            }
+            mcoder.input(0, 0);
+            mcoder.run();
+
+            for m in mcoder.metrics() {
+                assert_eq!((m.instructions, m.inputs, m.outputs), (6, 2, 1));
+                assert!(m.running > Duration::ZERO);
+            }
+
+            let summary = mcoder.summary();
+            let lines: Vec<&str> = summary.lines().collect();
+            assert_eq!(lines.len(), 6);
+            assert!(lines[0].starts_with("node instructions"));
+            assert!(lines[3].trim_start().starts_with("2            6        2        1"));
+            assert_eq!(summary.matches("<- bottleneck").count(), 1);
+        }
+
+        // machines that wait on the others spend time blocked
+        let mut mcoder = MultiCoder::scheduled(&[&prog[..]; 2], Schedule::RoundRobin);
+        mcoder.connect(0, 1);
+        mcoder.input(1, 1);
+        assert_eq!(mcoder.output(1), IntResponse::Input);
+        mcoder.input_all(0, &[0, 0]);
+        assert_eq!(mcoder.output(1), IntResponse::Output(1));
+        assert!(mcoder.metrics()[1].blocked > Duration::ZERO);
+    }
 }
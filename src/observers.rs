@@ -0,0 +1,164 @@
+use crate::intcoder::Observer;
+use std::collections::BTreeMap;
+
+
+/*
+ * Counts how many times the instruction at each address was executed
+ */
+#[derive(Debug, Default)]
+pub struct Coverage {
+    pub counts: BTreeMap<usize, u64>,
+    pub total: u64,
+}
+
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /*
+     * Number of distinct instructions executed at least once
+     */
+    pub fn covered(&self) -> usize {
+        self.counts.len()
+    }
+}
+
+
+impl Observer for Coverage {
+    fn on_instruction(&mut self, ip: usize, _instruction: i64) {
+        *self.counts.entry(ip).or_insert(0) += 1;
+        self.total += 1;
+    }
+}
+
+
+/*
+ * Counts instructions executed and values input and output, cheaply
+ * enough to leave on
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counter {
+    pub instructions: u64,
+    pub inputs: u64,
+    pub outputs: u64,
+}
+
+
+impl Counter {
+    pub fn new() -> Counter {
+        Counter::default()
+    }
+}
+
+
+impl Observer for Counter {
+    fn on_instruction(&mut self, _ip: usize, _instruction: i64) {
+        self.instructions += 1;
+    }
+
+    fn on_input(&mut self, _val: i64) {
+        self.inputs += 1;
+    }
+
+    fn on_output(&mut self, _val: i64) {
+        self.outputs += 1;
+    }
+}
+
+
+/*
+ * Records every hook call as a line of text, in order
+ */
+#[derive(Debug, Default)]
+pub struct Trace {
+    pub lines: Vec<String>,
+}
+
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace::default()
+    }
+}
+
+
+impl Observer for Trace {
+    fn on_instruction(&mut self, ip: usize, instruction: i64) {
+        self.lines.push(format!("{}: {}", ip, instruction));
+    }
+
+    fn on_read(&mut self, addr: usize, val: i64) {
+        self.lines.push(format!("  read [{}] = {}", addr, val));
+    }
+
+    fn on_write(&mut self, addr: usize, val: i64) {
+        self.lines.push(format!("  write [{}] = {}", addr, val));
+    }
+
+    fn on_input(&mut self, val: i64) {
+        self.lines.push(format!("  input {}", val));
+    }
+
+    fn on_output(&mut self, val: i64) {
+        self.lines.push(format!("  output {}", val));
+    }
+
+    fn on_halt(&mut self, ip: usize) {
+        self.lines.push(format!("{}: halt", ip));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcoder::{Intcode, IntResponse};
+
+    #[test]
+    fn test_trace() {
+        let eq_8 = vec!(3,9,8,9,10,9,4,9,99,-1,8);
+        let mut icoder = Intcode::new(&eq_8).observe(Trace::new());
+
+        assert!(matches!(icoder.run(), IntResponse::Input));
+        assert!(icoder.observer().lines.is_empty());
+
+        icoder.input(8);
+        assert!(matches!(icoder.run(), IntResponse::Output(1)));
+        assert!(matches!(icoder.run(), IntResponse::Halt));
+
+        assert_eq!(icoder.observer().lines, vec!(
+            "0: 3", "  input 8", "  write [9] = 8",
+            "2: 8", "  read [9] = 8", "  read [10] = 8", "  write [9] = 1",
+            "6: 4", "  read [9] = 1", "  output 1",
+            "8: 99", "8: halt",
+        ));
+    }
+
+    #[test]
+    fn test_coverage() {
+        let long = vec!(3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,
+                        1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,
+                        999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99);
+
+        let mut icoder = Intcode::new(&long).observe(Coverage::new());
+        icoder.start(8);
+        icoder.run();
+
+        let coverage = icoder.observer();
+        assert_eq!(coverage.counts.keys().cloned().collect::<Vec<usize>>(), vec!(0,2,6,22,26,28,46));
+        assert_eq!(coverage.total, 7);
+        assert_eq!(coverage.covered(), 7);
+    }
+
+    #[test]
+    fn test_counter() {
+        let eq_8 = vec!(3,9,8,9,10,9,4,9,99,-1,8);
+        let mut icoder = Intcode::new(&eq_8).observe(Counter::new());
+        assert_eq!(icoder.start(7), IntResponse::Output(0));
+        assert_eq!(icoder.run(), IntResponse::Halt);
+
+        assert_eq!(*icoder.observer(), Counter { instructions: 4, inputs: 1, outputs: 1 });
+    }
+}
@@ -0,0 +1,302 @@
+use crate::disasm::{self, Instr, Op, Param};
+use crate::intcoder::IntMode;
+use std::collections::{BTreeMap, BTreeSet};
+
+
+/*
+ * What optimize needs to know about a program before touching it: every
+ * instruction it can run, and every address it reads or writes as data
+ */
+struct Analysis {
+    instrs: BTreeMap<usize, Instr>,
+    reads: BTreeSet<usize>,
+    writes: BTreeSet<usize>,
+}
+
+
+/*
+ * Peephole optimizes a program, returning a copy with simpler instructions
+ * in place of ones it can prove always do the same thing. Every
+ * instruction keeps its length and address, so jumps and data are
+ * unaffected. Rewrites are:
+ *
+ * - positional reads of memory nothing ever writes become immediates,
+ *   including indirect jump targets
+ * - arithmetic on two constants becomes `add c, 0`
+ * - multiplying by 0 becomes `add 0, 0`, by 1 a copy `add x, 0`, and
+ *   `add 0, x` is turned round to the same copy
+ * - comparing an address with itself becomes a constant
+ * - a jump that always happens becomes `jnz 1, target`
+ *
+ * This is only sound if all the code can be found and none of it is ever
+ * written or read as data, so the program is returned unchanged if it
+ * uses relative mode anywhere (addresses can't be known), has a jump
+ * whose target can't be worked out, can reach something that doesn't
+ * decode or runs past the end of the image, has instructions that
+ * overlap, or writes into its own code. Memory
+ * is assumed to be plain memory, a program with devices mapped where it
+ * reads may behave differently.
+ */
+pub fn optimize(prog: &[i64]) -> Vec<i64> {
+    let analysis = match analyze(prog) {
+        Some(a) => a,
+        None => return prog.to_vec()
+    };
+
+    let mut out = prog.to_vec();
+
+    for instr in analysis.instrs.values() {
+        if (instr.addr..instr.next()).any(|a| analysis.reads.contains(&a)) {
+            continue;
+        }
+
+        let simpler = simplify(&fold(instr, prog, &analysis.writes));
+        out[instr.addr..instr.next()].copy_from_slice(&simpler.encode());
+    }
+
+    out
+}
+
+
+/*
+ * Finds all the code in prog and what it accesses, None if that can't be
+ * done with certainty. Jump targets read from memory that is never
+ * written are resolved as the search goes, so the code behind them is
+ * found too.
+ */
+fn analyze(prog: &[i64]) -> Option<Analysis> {
+    let mut code = prog.to_vec();
+    let mut resolved = BTreeSet::new();
+
+    loop {
+        let instrs = disasm::disassemble(&code);
+        let mut reads = BTreeSet::new();
+        let mut writes = BTreeSet::new();
+        let mut more = false;
+
+        for instr in instrs.values() {
+            for (i, p) in instr.params.iter().enumerate() {
+                match p.mode {
+                    IntMode::Rel => return None,
+                    IntMode::Imm => continue,
+                    IntMode::Pos if p.val < 0 => return None,
+                    IntMode::Pos if instr.op.writes() == Some(i) => writes.insert(p.val as usize),
+                    IntMode::Pos => reads.insert(p.val as usize)
+                };
+            }
+
+            if instr.is_jump() && instr.target().is_none() {
+                let addr = instr.params[1].val as usize;
+                let target = *prog.get(addr).filter(|t| **t >= 0)?;
+
+                // jump target parameter to immediate mode
+                code[instr.addr] += 1000;
+                code[instr.addr + 2] = target;
+                resolved.insert(addr);
+                more = true;
+            }
+        }
+
+        if more {
+            continue;
+        }
+
+        if ! complete(&instrs) {
+            return None;
+        }
+
+        let mut code_words = instrs.values().flat_map(|i| i.addr..i.next());
+        if code_words.any(|a| writes.contains(&a)) || resolved.iter().any(|a| writes.contains(a)) {
+            return None;
+        }
+
+        return Some(Analysis { instrs, reads: reads.into_iter().chain(resolved).collect(), writes });
+    }
+}
+
+
+/*
+ * Whether the disassembly accounts for every word control can reach:
+ * execution starts at a decoded instruction, everywhere it can go next
+ * was decoded too, which also rules out running off the end of the
+ * image, and no two instructions share a word
+ */
+fn complete(instrs: &BTreeMap<usize, Instr>) -> bool {
+    let mut end = 0;
+
+    if ! instrs.contains_key(&0) {
+        return false;
+    }
+
+    for instr in instrs.values() {
+        if instr.addr < end {
+            return false;
+        }
+
+        end = instr.next();
+
+        let falls = instr.op != Op::Halt && ! instr.always_jumps();
+        let mut succs = instr.target().into_iter().chain(Some(end).filter(|_| falls));
+
+        if ! succs.all(|s| instrs.contains_key(&s)) {
+            return false;
+        }
+    }
+
+    true
+}
+
+
+/*
+ * Instruction as decoded from the original program, with positional reads
+ * of never written memory replaced by the values there
+ */
+fn fold(instr: &Instr, prog: &[i64], writes: &BTreeSet<usize>) -> Instr {
+    let original = Instr::decode(prog, instr.addr).unwrap_or_else(|| instr.clone());
+    let mut instr = original.clone();
+
+    for (i, p) in instr.params.iter_mut().enumerate() {
+        if p.mode != IntMode::Pos || original.op.writes() == Some(i) {
+            continue;
+        }
+
+        let addr = p.val as usize;
+        if addr < prog.len() && ! writes.contains(&addr) {
+            *p = Param { mode: IntMode::Imm, val: prog[addr] };
+        }
+    }
+
+    instr
+}
+
+
+fn imm(val: i64) -> Param {
+    Param { mode: IntMode::Imm, val }
+}
+
+
+/*
+ * Same instruction in its simplest equivalent form of the same length
+ */
+fn simplify(instr: &Instr) -> Instr {
+    let addr = instr.addr;
+    let params = &instr.params;
+    let add = |a: Param, b: Param| Instr { addr, op: Op::Add, params: vec!(a, b, params[2]) };
+
+    if let Some(c) = instr.constant() {
+        return add(imm(c), imm(0));
+    }
+
+    match (instr.op, params.as_slice()) {
+        (Op::Mul, [Param { mode: IntMode::Imm, val: 0 }, _, _]) |
+        (Op::Mul, [_, Param { mode: IntMode::Imm, val: 0 }, _]) => add(imm(0), imm(0)),
+
+        (Op::Mul, [Param { mode: IntMode::Imm, val: 1 }, x, _]) |
+        (Op::Mul, [x, Param { mode: IntMode::Imm, val: 1 }, _]) |
+        (Op::Add, [Param { mode: IntMode::Imm, val: 0 }, x, _]) => add(*x, imm(0)),
+
+        (Op::Lt, [a, b, _]) if a.mode == IntMode::Pos && a == b => add(imm(0), imm(0)),
+        (Op::Eq, [a, b, _]) if a.mode == IntMode::Pos && a == b => add(imm(1), imm(0)),
+
+        (Op::Jnz, [_, target]) | (Op::Jz, [_, target]) if instr.always_jumps() => {
+            Instr { addr, op: Op::Jnz, params: vec!(imm(1), *target) }
+        },
+
+        _ => instr.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz;
+    use crate::intcoder::Intcode;
+
+    // the programs from intcoder::tests
+    const PROGRAMS: &[&[i64]] = &[
+        &[3,3,1108,-1,8,3,4,3,99],
+        &[3,3,1107,-1,8,3,4,3,99],
+        &[3,4,1007,0,-1,4,4,4,99],
+        &[3,9,8,9,10,9,4,9,99,-1,8],
+        &[3,9,7,9,10,9,4,9,99,-1,8],
+        &[3,11,5,11,12,104,1,99,104,0,99,0,8],
+        &[3,11,6,11,12,104,1,99,104,0,99,0,8],
+        &[3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9],
+        &[3,3,1105,0,8,104,1,99,104,0,99],
+        &[3,3,1106,11,8,104,1,99,104,0,99],
+        &[3,3,1105,-1,9,1101,0,0,12,4,12,99,1],
+        &[3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,
+          1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,
+          999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99],
+        &[109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99],
+        &[1102,34915192,34915192,7,4,7,99,0],
+        &[104,1125899906842624,99],
+        &[109,10,21102,3,3,0,4,10,99],
+        &[3,20,1006,20,14,1002,20,2,21,4,21,1105,1,0,99],
+        &[1,0,0,0,99,30,40,50],
+        &[3,11,3,12,1,11,12,13,4,13,99,0,0,0],
+    ];
+
+    #[test]
+    fn test_equivalent() {
+        let plain = |p: &[i64]| Intcode::new(p);
+        let optimized = |p: &[i64]| Intcode::new(&optimize(p));
+
+        for prog in PROGRAMS {
+            assert_eq!(optimize(prog).len(), prog.len());
+
+            for i in -2..12 {
+                let inputs = [i, 3, -4, 0];
+                let divergence = fuzz::compare(&plain, &optimized, prog, &inputs);
+                assert_eq!(divergence, None, "{:?} with {:?}", prog, inputs);
+            }
+        }
+
+        assert_eq!(fuzz::fuzz(&plain, &optimized, 38, 300), None);
+    }
+
+    #[test]
+    fn test_rewrites() {
+        // jump target read from memory, and adding a never written 1
+        let prog = PROGRAMS[7];
+        assert_eq!(optimize(prog), vec!(3,12,1006,12,9,1001,13,1,13,4,13,99,-1,0,1,9));
+
+        // eq of a never written 8 and the input
+        assert_eq!(optimize(PROGRAMS[3]), vec!(3,9,1008,9,8,9,4,9,99,-1,8));
+
+        // every other rewrite, on an input that can't be folded away
+        let mut prog = vec!(3,30, 1002,30,0,31, 102,1,30,32, 1101,4,5,33,
+                            8,30,30,34, 101,0,30,35, 1106,0,25, 99);
+        let mut want = vec!(3,30, 1101,0,0,31, 1001,30,0,32, 1101,9,0,33,
+                            1101,1,0,34, 1001,30,0,35, 1105,1,25, 99);
+        prog.resize(36, 0);
+        want.resize(36, 0);
+        assert_eq!(optimize(&prog), want);
+    }
+
+    #[test]
+    fn test_unsafe() {
+        // self-modifying, relative mode
+        for prog in [PROGRAMS[0], PROGRAMS[2], PROGRAMS[12], PROGRAMS[15]] {
+            assert_eq!(optimize(prog), prog);
+        }
+    }
+
+    #[test]
+    fn test_incomplete() {
+        // output at 3 runs past the end of the image and reads word 0
+        let prog = [5,4,3,4];
+        assert_eq!(optimize(&prog), prog);
+
+        // jumps to 2, inside the jump itself
+        let mut prog = vec!(6,19,16,23);
+        prog.resize(24, 0);
+        prog[16] = 2;
+        assert_eq!(optimize(&prog), prog);
+
+        // falls through into 1101 at 3 and jumps into the middle of it at 4
+        let prog = [1105,1,4,1101,1,4,99,99];
+        assert_eq!(optimize(&prog), prog);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::intcoder::{Engine, IntResponse};
+use std::collections::VecDeque;
+
+
+struct Stage<E: Engine> {
+    engine: E,
+    queue: VecDeque<i64>,
+    halted: bool,
+}
+
+
+/*
+ * Machines wired output to input and run on the calling thread. Each
+ * stage's outputs are queued as the next stage's inputs; in a feedback
+ * loop the last stage's outputs also go back to the first.
+ */
+pub struct Pipeline<E: Engine> {
+    stages: Vec<Stage<E>>,
+    feedback: bool,
+}
+
+
+/*
+ * Lets any engine start a chain with `a.pipe(b)`
+ */
+pub trait Pipe: Engine + Sized {
+    fn pipe(self, next: Self) -> Pipeline<Self> {
+        chain(vec!(self, next))
+    }
+}
+
+
+impl<E: Engine> Pipe for E {}
+
+
+/*
+ * Machines each feeding the next, the last one's outputs are the result
+ */
+pub fn chain<E: Engine>(engines: Vec<E>) -> Pipeline<E> {
+    Pipeline::new(engines, false)
+}
+
+
+/*
+ * Machines each feeding the next with the last feeding the first
+ */
+pub fn feedback_loop<E: Engine>(engines: Vec<E>) -> Pipeline<E> {
+    Pipeline::new(engines, true)
+}
+
+
+impl<E: Engine> Pipeline<E> {
+    fn new(engines: Vec<E>, feedback: bool) -> Pipeline<E> {
+        let stages = engines.into_iter().map(|engine| Stage {
+            engine,
+            queue: VecDeque::new(),
+            halted: false,
+        }).collect();
+
+        Pipeline { stages, feedback }
+    }
+
+    pub fn pipe(mut self, next: E) -> Pipeline<E> {
+        self.stages.push(Stage { engine: next, queue: VecDeque::new(), halted: false });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /*
+     * Queue an input for a stage, e.g. an amplifier's phase setting
+     */
+    pub fn send(&mut self, stage: usize, val: i64) {
+        self.stages[stage].queue.push_back(val);
+    }
+
+    pub fn halted(&self) -> bool {
+        self.stages.iter().all(|s| s.halted)
+    }
+
+    /*
+     * Runs stages in order, each until it halts or waits on an empty
+     * queue, and goes round again as long as any stage made progress.
+     * Returns everything the last stage output during the run.
+     */
+    pub fn run(&mut self) -> Vec<i64> {
+        let mut outputs = Vec::new();
+        let mut progress = true;
+        let last = self.stages.len().saturating_sub(1);
+
+        while progress {
+            progress = false;
+
+            for i in 0..self.stages.len() {
+                while ! self.stages[i].halted {
+                    let stage = &mut self.stages[i];
+
+                    let forward = match stage.engine.run() {
+                        IntResponse::Output(v) => v,
+                        IntResponse::Input => match stage.queue.pop_front() {
+                            Some(v) => {
+                                stage.engine.input(v);
+                                progress = true;
+                                continue;
+                            },
+                            None => break
+                        },
+                        IntResponse::Halt => {
+                            stage.halted = true;
+                            progress = true;
+                            break;
+                        }
+                    };
+
+                    progress = true;
+
+                    if i == last {
+                        outputs.push(forward);
+                    }
+
+                    if i < last {
+                        self.stages[i + 1].queue.push_back(forward);
+                    } else if self.feedback {
+                        self.stages[0].queue.push_back(forward);
+                    }
+                }
+            }
+        }
+
+        outputs
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcoder::Intcode;
+
+    fn amplifiers(prog: &[i64], phases: &[i64], feedback: bool) -> i64 {
+        let machines = phases.iter().map(|_| Intcode::new(prog)).collect();
+        let mut pipeline = if feedback { feedback_loop(machines) } else { chain(machines) };
+
+        for (i, p) in phases.iter().enumerate() {
+            pipeline.send(i, *p);
+        }
+        pipeline.send(0, 0);
+
+        let outputs = pipeline.run();
+        assert!(pipeline.halted());
+
+        *outputs.last().unwrap()
+    }
+
+    #[test]
+    fn test_pipe() {
+        // add one to each input
+        let inc = vec!(3,11,1001,11,1,11,4,11,1105,1,0,0);
+        let mut pipeline = Intcode::new(&inc).pipe(Intcode::new(&inc)).pipe(Intcode::new(&inc));
+        assert_eq!(pipeline.len(), 3);
+
+        pipeline.send(0, 1);
+        pipeline.send(0, 10);
+        assert_eq!(pipeline.run(), vec!(4, 13));
+        assert!(! pipeline.halted());
+
+        pipeline.send(0, -3);
+        assert_eq!(pipeline.run(), vec!(0));
+    }
+
+    #[test]
+    fn test_chain() {
+        let prog = vec!(3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0);
+        assert_eq!(amplifiers(&prog, &[4,3,2,1,0], false), 43210);
+
+        let prog = vec!(3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,
+                        1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0);
+        assert_eq!(amplifiers(&prog, &[1,0,4,3,2], false), 65210);
+    }
+
+    #[test]
+    fn test_feedback() {
+        let prog = vec!(3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,
+                        4,27,1001,28,-1,28,1005,28,6,99,0,0,5);
+        assert_eq!(amplifiers(&prog, &[9,8,7,6,5], true), 139629729);
+
+        let prog = vec!(3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,
+                        -5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,
+                        53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10);
+        assert_eq!(amplifiers(&prog, &[9,7,8,5,6], true), 18216);
+    }
+}
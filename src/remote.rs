@@ -0,0 +1,435 @@
+use crate::multicoder::{worker, Event, Machine, Metrics, Stats};
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+
+/*
+ * Where a node process finds its launcher, and which node it is
+ */
+pub const SOCKET_VAR: &str = "THREE_NODE_SOCKET";
+pub const ID_VAR: &str = "THREE_NODE_ID";
+
+
+// How long nodes get to connect back once started
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Largest frame payload read, so a corrupt length can't exhaust memory
+const MAX_PAYLOAD: u32 = 1 << 26;
+
+
+static SOCKETS: AtomicUsize = AtomicUsize::new(0);
+
+
+/*
+ * What goes over a node's socket. Each frame is a tag byte, the length of
+ * the payload as 4 bytes little endian, then the payload, which is words
+ * as 8 bytes little endian each, or text for Error.
+ *
+ * A node says Hello with its id, is sent its Program with the value to
+ * read from an empty queue, if any, and any values it boots with, then
+ * gets Input and reports back until it halts or fails. Its Metrics come
+ * just before each time it reports Blocked or Halt, durations in
+ * nanoseconds, with whether it's now waiting.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Hello(usize),
+    Program { empty: Option<i64>, boot: Vec<i64>, words: Vec<i64> },
+    Input(Vec<i64>),
+    Output(i64),
+    Blocked { consumed: u64, ip: usize },
+    Halt,
+    Error(String),
+    Metrics { metrics: Metrics, waiting: bool },
+}
+
+
+impl Frame {
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let (tag, words) = match self {
+            Frame::Hello(id) => (0, vec!(*id as i64)),
+            Frame::Program { empty, boot, words } => {
+                let mut payload = vec!(empty.is_some() as i64, empty.unwrap_or(0), boot.len() as i64);
+                payload.extend(boot);
+                payload.extend(words);
+                (1, payload)
+            },
+            Frame::Input(values) => (2, values.clone()),
+            Frame::Output(i) => (3, vec!(*i)),
+            Frame::Blocked { consumed, ip } => (4, vec!(*consumed as i64, *ip as i64)),
+            Frame::Halt => (5, Vec::new()),
+            Frame::Metrics { metrics: m, waiting } => (7, vec!(
+                m.instructions as i64, m.inputs as i64, m.outputs as i64,
+                m.blocked.as_nanos() as i64, m.running.as_nanos() as i64, *waiting as i64,
+            )),
+            Frame::Error(msg) => {
+                w.write_all(&[6])?;
+                w.write_all(&(msg.len() as u32).to_le_bytes())?;
+                return w.write_all(msg.as_bytes());
+            }
+        };
+
+        let mut bytes = vec!(tag);
+        bytes.extend(&((words.len() * 8) as u32).to_le_bytes());
+
+        for word in words {
+            bytes.extend(&word.to_le_bytes());
+        }
+
+        w.write_all(&bytes)
+    }
+
+    /*
+     * Next frame from r, None if it closed cleanly between frames
+     */
+    pub fn read<R: Read>(r: &mut R) -> io::Result<Option<Frame>> {
+        let mut tag = [0];
+
+        if r.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+
+        let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut len = [0; 4];
+        r.read_exact(&mut len)?;
+
+        let len = u32::from_le_bytes(len);
+        if len > MAX_PAYLOAD {
+            return Err(bad(&format!("frame of {} bytes is too large", len)));
+        }
+
+        let mut payload = Vec::new();
+        r.take(len as u64).read_to_end(&mut payload)?;
+
+        if payload.len() != len as usize {
+            return Err(bad("frame cut short"));
+        }
+
+        if tag[0] == 6 {
+            return match String::from_utf8(payload) {
+                Ok(msg) => Ok(Some(Frame::Error(msg))),
+                Err(_) => Err(bad("error frame isn't text"))
+            };
+        }
+
+        if ! payload.len().is_multiple_of(8) {
+            return Err(bad("frame isn't whole words"));
+        }
+
+        let words: Vec<i64> = payload.chunks(8).map(|c| {
+            let mut word = [0; 8];
+            word.copy_from_slice(c);
+            i64::from_le_bytes(word)
+        }).collect();
+
+        let frame = match (tag[0], &words[..]) {
+            (0, [id]) => Frame::Hello(*id as usize),
+            (1, [has_empty, empty, len, rest @ ..]) if *len >= 0 && *len as usize <= rest.len() => Frame::Program {
+                empty: if *has_empty != 0 { Some(*empty) } else { None },
+                boot: rest[..*len as usize].to_vec(),
+                words: rest[*len as usize..].to_vec(),
+            },
+            (2, values) => Frame::Input(values.to_vec()),
+            (3, [i]) => Frame::Output(*i),
+            (4, [consumed, ip]) => Frame::Blocked { consumed: *consumed as u64, ip: *ip as usize },
+            (5, []) => Frame::Halt,
+            (7, [instructions, inputs, outputs, blocked, running, waiting]) => Frame::Metrics {
+                metrics: Metrics {
+                    instructions: *instructions as u64,
+                    inputs: *inputs as u64,
+                    outputs: *outputs as u64,
+                    blocked: Duration::from_nanos(*blocked as u64),
+                    running: Duration::from_nanos(*running as u64),
+                },
+                waiting: *waiting != 0,
+            },
+            _ => return Err(bad(&format!("bad frame with tag {}", tag[0])))
+        };
+
+        Ok(Some(frame))
+    }
+}
+
+
+/*
+ * Runs one node of a network in this process, connecting back to the
+ * launcher named in the environment. A node whose machine panics, on a
+ * bad opcode say, reports the panic as an Error.
+ */
+pub fn node() -> io::Result<()> {
+    let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+
+    let path = env::var(SOCKET_VAR).map_err(|_| bad("no launcher socket given"))?;
+    let id = env::var(ID_VAR).ok().and_then(|id| id.parse().ok()).ok_or_else(|| bad("no node id given"))?;
+
+    let mut writer = UnixStream::connect(path)?;
+    let mut reader = writer.try_clone()?;
+    Frame::Hello(id).write(&mut writer)?;
+
+    let (empty, boot, words) = match Frame::read(&mut reader)? {
+        Some(Frame::Program { empty, boot, words }) => (empty, boot, words),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "expected a program"))
+    };
+
+    let (input_tx, input_rx) = channel();
+    let (events_tx, events_rx) = channel();
+
+    thread::spawn(move || {
+        while let Ok(Some(Frame::Input(values))) = Frame::read(&mut reader) {
+            if input_tx.send(values).is_err() {
+                return;
+            }
+        }
+    });
+
+    let stats = Stats::default();
+    let shared = stats.clone();
+
+    let sender = thread::spawn(move || {
+        for (_, event) in events_rx {
+            let frame = match event {
+                Event::Output(i) => Frame::Output(i),
+                Event::Blocked(consumed, ip) => Frame::Blocked { consumed, ip },
+                Event::Halt => Frame::Halt
+            };
+
+            if ! matches!(frame, Frame::Output(_)) {
+                let (metrics, since) = *shared.lock().unwrap();
+
+                if (Frame::Metrics { metrics, waiting: since.is_some() }).write(&mut writer).is_err() {
+                    break;
+                }
+            }
+
+            if frame.write(&mut writer).is_err() {
+                break;
+            }
+        }
+
+        writer
+    });
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        worker(Machine::new(&words, empty, boot), id, input_rx, events_tx, stats)
+    }));
+
+    let mut writer = sender.join().map_err(|_| bad("sender failed"))?;
+
+    if let Err(panic) = result {
+        let msg = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+            (Some(msg), _) => msg.to_string(),
+            (_, Some(msg)) => msg.clone(),
+            _ => "machine panicked".to_string()
+        };
+
+        Frame::Error(msg).write(&mut writer)?;
+    }
+
+    Ok(())
+}
+
+
+/*
+ * The launcher's end of a network of node processes, looking just like
+ * machines on threads from the outside
+ */
+pub(crate) struct Launched {
+    pub inputs: Vec<Sender<Vec<i64>>>,
+    pub events: Receiver<(usize, Event)>,
+    pub children: Vec<Child>,
+    pub errors: Arc<Mutex<Vec<Option<String>>>>,
+    pub stats: Vec<Stats>,
+}
+
+
+/*
+ * Starts a node process for each program by running exe with args and
+ * the socket and id in the environment, and waits for them all to
+ * connect. Gives up, killing what it started, if any of them exit or
+ * don't connect in time.
+ */
+pub(crate) fn launch(exe: &Path, args: &[&str], progs: &[&[i64]], empty: Option<i64>, boot: Vec<Vec<i64>>) -> io::Result<Launched> {
+    let path = env::temp_dir().join(format!("three-{}-{}.sock", std::process::id(), SOCKETS.fetch_add(1, Ordering::SeqCst)));
+    let _ = fs::remove_file(&path);
+
+    let listener = UnixListener::bind(&path)?;
+    let mut children = Vec::new();
+
+    let accepted = (|| {
+        for id in 0..progs.len() {
+            children.push(Command::new(exe)
+                .args(args)
+                .env(SOCKET_VAR, &path)
+                .env(ID_VAR, id.to_string())
+                .stdout(Stdio::null())
+                .spawn()?);
+        }
+
+        accept(&listener, &mut children)
+    })();
+
+    let _ = fs::remove_file(&path);
+
+    let streams = match accepted {
+        Ok(streams) => streams,
+        Err(e) => {
+            for child in children.iter_mut() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            return Err(e);
+        }
+    };
+
+    let (events_tx, events) = channel();
+    let errors = Arc::new(Mutex::new(vec!(None; progs.len())));
+    let stats: Vec<Stats> = progs.iter().map(|_| Stats::default()).collect();
+    let mut inputs = Vec::new();
+
+    for (id, (mut stream, (prog, boot))) in streams.into_iter().zip(progs.iter().zip(boot)).enumerate() {
+        Frame::Program { empty, boot, words: prog.to_vec() }.write(&mut stream)?;
+
+        let (input_tx, input_rx) = channel::<Vec<i64>>();
+        let mut reader = stream.try_clone()?;
+        let events_tx = events_tx.clone();
+        let errors = errors.clone();
+        let stats = stats[id].clone();
+
+        thread::spawn(move || {
+            for values in input_rx {
+                if Frame::Input(values).write(&mut stream).is_err() {
+                    return;
+                }
+            }
+        });
+
+        thread::spawn(move || loop {
+            let event = match Frame::read(&mut reader) {
+                Ok(Some(Frame::Output(i))) => Event::Output(i),
+                Ok(Some(Frame::Blocked { consumed, ip })) => Event::Blocked(consumed, ip),
+                Ok(Some(Frame::Halt)) => Event::Halt,
+                Ok(Some(Frame::Metrics { metrics, waiting })) => {
+                    *stats.lock().unwrap() = (metrics, if waiting { Some(Instant::now()) } else { None });
+                    continue;
+                },
+                other => {
+                    let msg = match other {
+                        Ok(Some(Frame::Error(msg))) => msg,
+                        Ok(None) => "node exited without halting".to_string(),
+                        Ok(Some(frame)) => format!("unexpected {:?} from node", frame),
+                        Err(e) => e.to_string()
+                    };
+
+                    errors.lock().unwrap()[id] = Some(msg);
+                    Event::Halt
+                }
+            };
+
+            if events_tx.send((id, event)).is_err() || event == Event::Halt {
+                return;
+            }
+        });
+
+        inputs.push(input_tx);
+    }
+
+    Ok(Launched { inputs, events, children, errors, stats })
+}
+
+
+/*
+ * A stream from each child in id order, once they've all said hello
+ */
+fn accept(listener: &UnixListener, children: &mut [Child]) -> io::Result<Vec<UnixStream>> {
+    let mut streams: Vec<Option<UnixStream>> = children.iter().map(|_| None).collect();
+    let started = Instant::now();
+    let failed = |msg: String| io::Error::other(msg);
+
+    listener.set_nonblocking(true)?;
+
+    while streams.iter().any(|s| s.is_none()) {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+
+                match Frame::read(&mut stream)? {
+                    Some(Frame::Hello(id)) if id < streams.len() && streams[id].is_none() => {
+                        stream.set_read_timeout(None)?;
+                        streams[id] = Some(stream);
+                    },
+                    frame => return Err(failed(format!("bad hello from node: {:?}", frame)))
+                };
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                for (id, child) in children.iter_mut().enumerate() {
+                    if let Some(status) = child.try_wait()? {
+                        return Err(failed(format!("node {} exited before connecting: {}", id, status)));
+                    }
+                }
+
+                if started.elapsed() > CONNECT_TIMEOUT {
+                    return Err(failed("nodes didn't connect in time".to_string()));
+                }
+
+                thread::sleep(Duration::from_millis(5));
+            },
+            Err(e) => return Err(e)
+        };
+    }
+
+    Ok(streams.into_iter().flatten().collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let frames = [
+            Frame::Hello(3),
+            Frame::Program { empty: Some(-1), boot: vec!(7), words: vec!(3,0,99) },
+            Frame::Program { empty: None, boot: vec!(), words: vec!() },
+            Frame::Input(vec!(i64::MIN, 0, i64::MAX)),
+            Frame::Output(-5),
+            Frame::Blocked { consumed: 4, ip: 12 },
+            Frame::Halt,
+            Frame::Error("bad opcode".to_string()),
+            Frame::Metrics {
+                metrics: Metrics {
+                    instructions: 100, inputs: 3, outputs: 2,
+                    blocked: Duration::from_micros(5), running: Duration::from_nanos(1234),
+                },
+                waiting: true,
+            },
+        ];
+
+        let mut bytes = Vec::new();
+        for frame in frames.iter() {
+            frame.write(&mut bytes).unwrap();
+        }
+
+        let mut r = &bytes[..];
+        for frame in frames.iter() {
+            assert_eq!(Frame::read(&mut r).unwrap().as_ref(), Some(frame));
+        }
+        assert_eq!(Frame::read(&mut r).unwrap(), None);
+
+        assert!(Frame::read(&mut &[3, 8, 0, 0, 0, 1][..]).is_err());
+        assert!(Frame::read(&mut &[9, 0, 0, 0, 0][..]).is_err());
+        assert!(Frame::read(&mut &[2, 0, 0, 0, 0xff, 0][..]).is_err());
+    }
+}
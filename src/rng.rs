@@ -0,0 +1,30 @@
+/*
+ * Small xorshift generator, shared by the fuzzer and the random device, so
+ * sequences are reproducible from a seed without pulling in a dependency
+ */
+pub struct Rng(u64);
+
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn bits(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /*
+     * Uniform value in lo..=hi
+     */
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.bits() % ((hi - lo + 1) as u64)) as i64
+    }
+
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.bits() % 100 < percent
+    }
+}
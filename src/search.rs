@@ -0,0 +1,157 @@
+use crate::intcoder::{Intcode, IntResponse, Patch};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::thread;
+
+
+/*
+ * One point in a search space: memory patches to apply before running
+ * and inputs to feed the program in order
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Candidate {
+    pub patches: Vec<Patch>,
+    pub inputs: Vec<i64>,
+}
+
+
+impl Candidate {
+    pub fn inputs(inputs: &[i64]) -> Candidate {
+        Candidate { patches: Vec::new(), inputs: inputs.to_vec() }
+    }
+
+    pub fn patches(patches: &[Patch]) -> Candidate {
+        Candidate { patches: patches.to_vec(), inputs: Vec::new() }
+    }
+
+    /*
+     * Runs prog with this candidate until it halts or wants more input
+     * than the candidate has, returning the machine and its outputs
+     */
+    pub fn run(&self, prog: &[i64]) -> (Intcode, Vec<i64>) {
+        let mut icoder = Intcode::with_patches(prog, &self.patches);
+        let mut inputs = self.inputs.iter();
+        let mut outputs = Vec::new();
+
+        loop {
+            match icoder.run() {
+                IntResponse::Output(i) => outputs.push(i),
+                IntResponse::Input => match inputs.next() {
+                    Some(i) => { icoder.input(*i); },
+                    None => break
+                },
+                IntResponse::Halt => break
+            };
+        }
+
+        (icoder, outputs)
+    }
+}
+
+
+/*
+ * Every combination of values for the given addresses, the last address
+ * changing fastest. grid(&[1, 2], 0..100) is the noun/verb space.
+ */
+pub fn grid(addrs: &[usize], values: std::ops::Range<i64>) -> impl Iterator<Item = Candidate> {
+    let addrs = addrs.to_vec();
+    let width = (values.end - values.start).max(0) as u64;
+    let total = width.checked_pow(addrs.len() as u32).unwrap_or(u64::MAX);
+
+    (0..total).map(move |mut n| {
+        let mut patches = vec!(Patch { addr: 0, val: 0 }; addrs.len());
+
+        for (i, addr) in addrs.iter().enumerate().rev() {
+            patches[i] = Patch { addr: *addr, val: values.start + (n % width) as i64 };
+            n /= width;
+        }
+
+        Candidate::patches(&patches)
+    })
+}
+
+
+/*
+ * Tries candidates on prog across a pool of threads and returns the first
+ * one, in the order the candidates are given, for which matches returns
+ * true. matches gets the machine after the run, to look at its memory,
+ * along with everything it output.
+ *
+ * Workers stop taking candidates as soon as one past a known match would
+ * be next, so the search ends early without losing determinism. Runs that
+ * panic (bad opcodes from a bad patch, say) simply don't match, but a
+ * candidate that sends the program into an endless loop will hang its
+ * worker.
+ */
+pub fn search<I, P>(prog: &[i64], candidates: I, threads: usize, matches: P) -> Option<Candidate>
+    where I: Iterator<Item = Candidate> + Send, P: Fn(&Intcode, &[i64]) -> bool + Sync {
+    let queue = Mutex::new(candidates.enumerate());
+    let best: Mutex<Option<(usize, Candidate)>> = Mutex::new(None);
+
+    thread::scope(|s| {
+        for _ in 0..threads.max(1) {
+            s.spawn(|| loop {
+                let (index, candidate) = {
+                    let mut queue = queue.lock().unwrap();
+                    let found = best.lock().unwrap().as_ref().map(|(i, _)| *i);
+
+                    match queue.next() {
+                        Some((i, _)) if found.is_some_and(|f| i > f) => return,
+                        Some(next) => next,
+                        None => return
+                    }
+                };
+
+                let hit = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let (icoder, outputs) = candidate.run(prog);
+                    matches(&icoder, &outputs)
+                }));
+
+                if let Ok(true) = hit {
+                    let mut best = best.lock().unwrap();
+
+                    if best.as_ref().is_none_or(|(i, _)| index < *i) {
+                        *best = Some((index, candidate));
+                    }
+                }
+            });
+        }
+    });
+
+    best.into_inner().unwrap().map(|(_, c)| c)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid() {
+        let space: Vec<Candidate> = grid(&[1, 2], 0..3).collect();
+        assert_eq!(space.len(), 9);
+        assert_eq!(space[5].patches, vec!(Patch { addr: 1, val: 1 }, Patch { addr: 2, val: 2 }));
+    }
+
+    #[test]
+    fn test_noun_verb() {
+        // mem[0] = noun * verb + 7
+        let prog = vec!(1102,0,0,0,1001,0,7,0,99);
+        let found = search(&prog, grid(&[1, 2], 0..100), 4, |icoder, _| icoder.read(0) == 97 * 89 + 7);
+
+        let found = found.unwrap();
+        assert_eq!(found.patches, vec!(Patch { addr: 1, val: 89 }, Patch { addr: 2, val: 97 }));
+
+        assert_eq!(search(&prog, grid(&[1, 2], 0..10), 4, |icoder, _| icoder.read(0) < 0), None);
+    }
+
+    #[test]
+    fn test_inputs() {
+        // output the square of the input
+        let prog = vec!(3,9,2,9,9,9,4,9,99,0);
+        let space = (-10..10).map(|i| Candidate::inputs(&[i]));
+        let found = search(&prog, space, 3, |_, out| out == [49]);
+
+        assert_eq!(found, Some(Candidate::inputs(&[-7])));
+    }
+}
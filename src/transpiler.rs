@@ -0,0 +1,281 @@
+use crate::disasm::{self, Instr, Op, Param};
+use crate::intcoder::IntMode;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+
+/*
+ * Translates a program image into the source of a Rust module with a
+ * `Program` type that behaves like an Intcode loaded with the same image:
+ * `new`, `input`, `start`, `run` and `run_for` work the same way and
+ * return the same IntResponse values. It also implements Engine.
+ *
+ * Every instruction found by disassembling the image becomes one arm of a
+ * match over the instruction pointer. Memory is still a plain vector, so
+ * the program can read and write data as usual, but a write to any word
+ * of translated code, or a jump to an address that wasn't translated,
+ * hands the machine state over to an interpreter which runs it from then
+ * on.
+ *
+ * `intcoder` is the path the generated module should import the
+ * interpreter from, e.g. `three::intcoder` or `crate::intcoder`.
+ */
+pub fn transpile(prog: &[i64], intcoder: &str) -> String {
+    let instrs = disasm::disassemble(prog);
+    let mut code = BTreeSet::new();
+    let mut out = String::new();
+
+    for instr in instrs.values() {
+        code.extend(instr.addr..instr.next());
+    }
+
+    let image: Vec<String> = prog.iter().map(|v| v.to_string()).collect();
+    let flags: Vec<&str> = (0..prog.len()).map(|a| if code.contains(&a) { "true" } else { "false" }).collect();
+
+    writeln!(out, "// Generated from an Intcode program image, do not edit").unwrap();
+    writeln!(out, "#![allow(clippy::all, unused)]").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "use {}::{{Engine, Intcode, IntResponse, PADDING}};", intcoder).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "static IMAGE: [i64; {}] = [{}];", prog.len(), image.join(",")).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "// Words of IMAGE that were translated as instructions").unwrap();
+    writeln!(out, "static CODE: [bool; {}] = [{}];", prog.len(), flags.join(",")).unwrap();
+    out.push_str(PRELUDE);
+
+    for instr in instrs.values() {
+        writeln!(out, "                // {}", instr).unwrap();
+        writeln!(out, "                {} => {{", instr.addr).unwrap();
+        for line in body(instr) {
+            writeln!(out, "                    {}", line).unwrap();
+        }
+        writeln!(out, "                }},").unwrap();
+    }
+
+    out.push_str(POSTLUDE);
+    out
+}
+
+
+/*
+ * Statements for a single match arm, ending with the next ip
+ */
+fn body(instr: &Instr) -> Vec<String> {
+    let p = |i: usize| read(&instr.params[i]);
+    let next = instr.next();
+
+    match instr.op {
+        Op::Add | Op::Mul | Op::Lt | Op::Eq => {
+            let value = match instr.op {
+                Op::Add => format!("{} + {}", p(0), p(1)),
+                Op::Mul => format!("{} * {}", p(0), p(1)),
+                Op::Lt => format!("({} < {}) as i64", p(0), p(1)),
+                _ => format!("({} == {}) as i64", p(0), p(1)),
+            };
+
+            vec!(
+                format!("let value = {};", value),
+                format!("self.ip = {};", next),
+                format!("self.store({}, value);", addr(&instr.params[2])),
+            )
+        },
+        Op::In => vec!(
+            "let value = match self.input.take() {".to_string(),
+            "    Some(i) => i,".to_string(),
+            "    None => return Some(IntResponse::Input)".to_string(),
+            "};".to_string(),
+            format!("self.ip = {};", next),
+            format!("self.store({}, value);", addr(&instr.params[0])),
+        ),
+        Op::Out => vec!(
+            format!("let value = {};", p(0)),
+            format!("self.ip = {};", next),
+            "return Some(IntResponse::Output(value));".to_string(),
+        ),
+        Op::Jnz | Op::Jz => {
+            let test = if instr.op == Op::Jnz { "!=" } else { "==" };
+
+            vec!(format!("self.ip = if {} {} 0 {{ {} as usize }} else {{ {} }};", p(0), test, p(1), next))
+        },
+        Op::Rel => vec!(
+            format!("self.rel += {};", p(0)),
+            format!("self.ip = {};", next),
+        ),
+        Op::Halt => vec!(
+            format!("self.ip = {};", next),
+            "return Some(IntResponse::Halt);".to_string(),
+        ),
+    }
+}
+
+
+/*
+ * Expression for the address a parameter refers to
+ */
+fn addr(param: &Param) -> String {
+    match param.mode {
+        IntMode::Rel => format!("(self.rel + {}) as usize", param.val),
+        _ if param.val < 0 => format!("({}i64) as usize", param.val),
+        _ => param.val.to_string(),
+    }
+}
+
+
+/*
+ * Expression for the value of a parameter
+ */
+fn read(param: &Param) -> String {
+    match param.mode {
+        IntMode::Imm => format!("({}i64)", param.val),
+        _ => format!("self.mem[{}]", addr(param)),
+    }
+}
+
+
+const PRELUDE: &str = r#"
+pub struct Program {
+    mem: Vec<i64>,
+    ip: usize,
+    rel: i64,
+    input: Option<i64>,
+    modified: bool,
+    fallback: Option<Intcode>,
+}
+
+impl Program {
+
+    pub fn new() -> Program {
+        let mut mem = IMAGE.to_vec();
+        mem.resize(IMAGE.len() + PADDING, 0);
+
+        Program {
+            mem,
+            ip: 0,
+            rel: 0,
+            input: None,
+            modified: false,
+            fallback: None,
+        }
+    }
+
+    pub fn input(&mut self, num: i64) -> bool {
+        if let Some(vm) = &mut self.fallback {
+            return vm.input(num);
+        }
+
+        if self.input.is_some() {
+            return false;
+        }
+
+        self.input = Some(num);
+
+        true
+    }
+
+    pub fn start(&mut self, input: i64) -> IntResponse {
+        self.input(input);
+
+        self.run()
+    }
+
+    fn store(&mut self, addr: usize, val: i64) {
+        self.mem[addr] = val;
+
+        if addr < CODE.len() && CODE[addr] {
+            self.modified = true;
+        }
+    }
+
+    /*
+     * Hand the machine over to the interpreter for good, to run what's
+     * left of the steps
+     */
+    fn interpret(&mut self, steps: u64) -> Option<IntResponse> {
+        let mut vm = Intcode::resume(std::mem::take(&mut self.mem), self.ip, self.rel);
+
+        if let Some(i) = self.input.take() {
+            vm.input(i);
+        }
+
+        let response = vm.run_for(steps);
+        self.fallback = Some(vm);
+
+        response
+    }
+
+    pub fn run(&mut self) -> IntResponse {
+        loop {
+            if let Some(response) = self.run_for(u64::MAX) {
+                return response;
+            }
+        }
+    }
+
+    pub fn run_for(&mut self, steps: u64) -> Option<IntResponse> {
+        if let Some(vm) = &mut self.fallback {
+            return vm.run_for(steps);
+        }
+
+        for step in 0..steps {
+            if self.modified {
+                return self.interpret(steps - step);
+            }
+
+            match self.ip {
+"#;
+
+
+const POSTLUDE: &str = r#"                _ => return self.interpret(steps - step),
+            }
+        }
+
+        None
+    }
+}
+
+impl Engine for Program {
+    fn input(&mut self, num: i64) -> bool {
+        Program::input(self, num)
+    }
+
+    fn run(&mut self) -> IntResponse {
+        Program::run(self)
+    }
+
+    fn run_for(&mut self, steps: u64) -> Option<IntResponse> {
+        Program::run_for(self, steps)
+    }
+}
+"#;
+
+
+// Checked in output of transpile for COUNTDOWN, so it gets built and run
+#[cfg(test)]
+mod countdown;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::{self, Event};
+    use crate::intcoder::Intcode;
+
+    // Outputs its input down to 1, looping back through a relative jump
+    const COUNTDOWN: [i64; 18] = [109,50,203,50,1206,50,16,204,50,21201,50,-1,50,2105,1,-33,99,4];
+
+    #[test]
+    fn test_source() {
+        assert_eq!(transpile(&COUNTDOWN, "crate::intcoder"), include_str!("transpiler/countdown.rs"));
+    }
+
+    #[test]
+    fn test_countdown() {
+        let program = |_: &[i64]| countdown::Program::new();
+        let events = fuzz::trace(&program, &COUNTDOWN, &[3]);
+        assert_eq!(events, vec!(Event::Input(3), Event::Output(3), Event::Output(2), Event::Output(1), Event::Halt));
+
+        for inputs in [vec!(0), vec!(1), vec!(7), vec!()].iter() {
+            assert_eq!(fuzz::compare(&program, &Intcode::new, &COUNTDOWN, inputs), None);
+        }
+    }
+}
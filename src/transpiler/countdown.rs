@@ -0,0 +1,159 @@
+// Generated from an Intcode program image, do not edit
+#![allow(clippy::all, unused)]
+
+use crate::intcoder::{Engine, Intcode, IntResponse, PADDING};
+
+static IMAGE: [i64; 18] = [109,50,203,50,1206,50,16,204,50,21201,50,-1,50,2105,1,-33,99,4];
+
+// Words of IMAGE that were translated as instructions
+static CODE: [bool; 18] = [true,true,true,true,true,true,true,true,true,true,true,true,true,true,true,true,true,false];
+
+pub struct Program {
+    mem: Vec<i64>,
+    ip: usize,
+    rel: i64,
+    input: Option<i64>,
+    modified: bool,
+    fallback: Option<Intcode>,
+}
+
+impl Program {
+
+    pub fn new() -> Program {
+        let mut mem = IMAGE.to_vec();
+        mem.resize(IMAGE.len() + PADDING, 0);
+
+        Program {
+            mem,
+            ip: 0,
+            rel: 0,
+            input: None,
+            modified: false,
+            fallback: None,
+        }
+    }
+
+    pub fn input(&mut self, num: i64) -> bool {
+        if let Some(vm) = &mut self.fallback {
+            return vm.input(num);
+        }
+
+        if self.input.is_some() {
+            return false;
+        }
+
+        self.input = Some(num);
+
+        true
+    }
+
+    pub fn start(&mut self, input: i64) -> IntResponse {
+        self.input(input);
+
+        self.run()
+    }
+
+    fn store(&mut self, addr: usize, val: i64) {
+        self.mem[addr] = val;
+
+        if addr < CODE.len() && CODE[addr] {
+            self.modified = true;
+        }
+    }
+
+    /*
+     * Hand the machine over to the interpreter for good, to run what's
+     * left of the steps
+     */
+    fn interpret(&mut self, steps: u64) -> Option<IntResponse> {
+        let mut vm = Intcode::resume(std::mem::take(&mut self.mem), self.ip, self.rel);
+
+        if let Some(i) = self.input.take() {
+            vm.input(i);
+        }
+
+        let response = vm.run_for(steps);
+        self.fallback = Some(vm);
+
+        response
+    }
+
+    pub fn run(&mut self) -> IntResponse {
+        loop {
+            if let Some(response) = self.run_for(u64::MAX) {
+                return response;
+            }
+        }
+    }
+
+    pub fn run_for(&mut self, steps: u64) -> Option<IntResponse> {
+        if let Some(vm) = &mut self.fallback {
+            return vm.run_for(steps);
+        }
+
+        for step in 0..steps {
+            if self.modified {
+                return self.interpret(steps - step);
+            }
+
+            match self.ip {
+                // arb 50
+                0 => {
+                    self.rel += (50i64);
+                    self.ip = 2;
+                },
+                // in [rb+50]
+                2 => {
+                    let value = match self.input.take() {
+                        Some(i) => i,
+                        None => return Some(IntResponse::Input)
+                    };
+                    self.ip = 4;
+                    self.store((self.rel + 50) as usize, value);
+                },
+                // jz [rb+50], 16
+                4 => {
+                    self.ip = if self.mem[(self.rel + 50) as usize] == 0 { (16i64) as usize } else { 7 };
+                },
+                // out [rb+50]
+                7 => {
+                    let value = self.mem[(self.rel + 50) as usize];
+                    self.ip = 9;
+                    return Some(IntResponse::Output(value));
+                },
+                // add [rb+50], -1, [rb+50]
+                9 => {
+                    let value = self.mem[(self.rel + 50) as usize] + (-1i64);
+                    self.ip = 13;
+                    self.store((self.rel + 50) as usize, value);
+                },
+                // jnz 1, [rb-33]
+                13 => {
+                    self.ip = if (1i64) != 0 { self.mem[(self.rel + -33) as usize] as usize } else { 16 };
+                },
+                // halt
+                16 => {
+                    self.ip = 17;
+                    return Some(IntResponse::Halt);
+                },
+                _ => return self.interpret(steps - step),
+            }
+        }
+
+        None
+    }
+}
+
+impl Engine for Program {
+    fn input(&mut self, num: i64) -> bool {
+        Program::input(self, num)
+    }
+
+    fn run(&mut self) -> IntResponse {
+        Program::run(self)
+    }
+
+    fn run_for(&mut self, steps: u64) -> Option<IntResponse> {
+        Program::run_for(self, steps)
+    }
+}
@@ -0,0 +1,45 @@
+use std::path::Path;
+use three::intcoder::IntResponse;
+use three::multicoder::MultiCoder;
+
+
+/*
+ * A network with each machine in a `three node` process
+ */
+fn launch(progs: &[&[i64]]) -> MultiCoder {
+    MultiCoder::processes(Path::new(env!("CARGO_BIN_EXE_three")), &["node"], progs).unwrap()
+}
+
+
+#[test]
+fn test_processes() {
+    let prog = vec!(3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,
+                    4,27,1001,28,-1,28,1005,28,6,99,0,0,5);
+    let mut mcoder = launch(&[&prog[..]; 5]);
+    mcoder.ring();
+
+    for (i, p) in [9,8,7,6,5].iter().enumerate() {
+        mcoder.input(i, *p);
+    }
+    mcoder.input(0, 0);
+
+    assert_eq!(mcoder.run()[4].last(), Some(&139629729));
+    assert!((0..5).all(|i| mcoder.error(i).is_none()));
+
+    // each amplifier goes round the loop 5 times
+    let metrics = mcoder.metrics();
+    assert_eq!(metrics[0].instructions, 2 + 5 * 6 + 1);
+    assert!(metrics.iter().all(|m| m.inputs == 6 && m.outputs == 5));
+}
+
+
+#[test]
+fn test_node_failure() {
+    // outputs its input, then runs into a bad opcode
+    let echo = [3,9,4,9,42];
+    let mut mcoder = launch(&[&echo[..]]);
+
+    assert_eq!(mcoder.send(0, 8), IntResponse::Output(8));
+    assert_eq!(mcoder.output(0), IntResponse::Halt);
+    assert!(mcoder.error(0).unwrap().contains("42"));
+}